@@ -1,50 +1,186 @@
 use core::{fmt::Debug, hash::Hash, ops::Range};
-use enum_map::{enum_map, Enum, EnumMap};
-use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashSet};
+use serde::{de, ser, Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    sync::OnceLock,
+};
 
-const KIND_BITS_LEN: u8 = 2;
-const KIND_METAS: &'static [FactKindMeta] = &[
-    FactKindMeta {
-        kind_name: "owner",
-        field_metas: &[FactFieldMeta { field_name: "owner", bits_len: 2 }],
-    },
-    FactKindMeta {
-        kind_name: "friend",
-        field_metas: &[
-            FactFieldMeta { field_name: "a", bits_len: 2 },
-            FactFieldMeta { field_name: "b", bits_len: 2 },
-        ],
-    },
-];
+/// Used when no schema file is given on the command line.
+const DEFAULT_SCHEMA: &str = include_str!("../schema.ron");
+static SCHEMA: OnceLock<Schema> = OnceLock::new();
+/// Graphviz colours given to agents in DOT exports, in order of appearance.
+const DOT_COLOURS: &[&str] =
+    &["red", "blue", "darkgreen", "orange", "purple", "brown", "magenta", "cyan4"];
+#[derive(Debug, Serialize, Deserialize)]
 struct FactFieldMeta {
-    field_name: &'static str,
-    bits_len: u8,
+    field_name: String,
+    /// Field values range over `0..domain`.
+    domain: u32,
 }
-#[derive(Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize)]
 struct FactKindMeta {
-    kind_name: &'static str,
-    field_metas: &'static [FactFieldMeta],
+    kind_name: String,
+    field_metas: Vec<FactFieldMeta>,
 }
-struct FactHr(Fact);
-struct HeapPermute<'a, T> {
-    arr: &'a mut [T],
-    i: usize,
-    n: usize,
-    c: Vec<usize>,
+/// Stands for a value given to a field of a `FactTemplate`.
+#[derive(Debug, Serialize, Deserialize)]
+enum Term {
+    Param(String),
+    Value(u32),
+}
+/// Describes facts of kind `kind_name`. Fields missing from `fields` match any value.
+#[derive(Debug, Serialize, Deserialize)]
+struct FactTemplate {
+    kind_name: String,
+    fields: BTreeMap<String, Term>,
+}
+/// Declares the effects of events named `event_name`, given arguments for `params`.
+/// The event can only occur if each of `require` matches some true fact,
+/// and none of `forbid` does. Then, facts matching `terminate` become false,
+/// and then facts in `create` become true.
+#[derive(Debug, Serialize, Deserialize)]
+struct EventDef {
+    event_name: String,
+    params: Vec<String>,
+    #[serde(default)]
+    require: Vec<FactTemplate>,
+    #[serde(default)]
+    forbid: Vec<FactTemplate>,
+    #[serde(default)]
+    terminate: Vec<FactTemplate>,
+    #[serde(default)]
+    create: Vec<FactTemplate>,
 }
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+/// Declares the fact kinds and events of the modelled domain. Loaded once at startup.
+#[derive(Debug, Serialize, Deserialize)]
+struct Schema {
+    kind_metas: Vec<FactKindMeta>,
+    #[serde(default)]
+    event_defs: Vec<EventDef>,
+}
+#[derive(Debug)]
+enum SchemaError {
+    Io(std::io::Error),
+    Parse(ron::Error),
+    TooManyKinds { kinds_len: usize },
+    FactTooWide { kind_name: String, bits_len: u32 },
+    EmptyDomain { kind_name: String, field_name: String },
+    DuplicateEvent { event_name: String },
+    UnknownKind { event_name: String, kind_name: String },
+    UnknownField { event_name: String, kind_name: String, field_name: String },
+    UnknownParam { event_name: String, param: String },
+    UnboundField { event_name: String, kind_name: String, field_name: String },
+}
+struct FactHr(Fact);
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Serialize, Deserialize)]
 struct EventInstance {
     event: Event,
     index: u32,
 }
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
-enum Event {
-    SetOwner { owner: u32 },
-    BecomeFriends { a: u32, b: u32 },
+/// An occurrence of the schema's `EventDef` named `name`, with `args` given for its params.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Serialize, Deserialize)]
+struct Event {
+    name: String,
+    args: Vec<u32>,
+}
+/// An ordering that could not be completed, because `event_instance` cannot occur in `situation`.
+#[derive(Debug)]
+struct Blocked {
+    ordering: Vec<EventInstance>,
+    event_instance: EventInstance,
+    situation: Situation,
+}
+#[derive(Debug, Default)]
+struct Outcomes {
+    destinations: BTreeMap<Situation, Vec<EventInstance>>,
+    blocked: Vec<Blocked>,
+}
+/// A fact true in some destinations but not others, and the unordered pairs of
+/// events whose swap, where they are adjacent, changes its truth in the destination.
+#[derive(Debug)]
+struct Divergence {
+    fact: Fact,
+    deciding_pairs: Vec<[EventInstance; 2]>,
+}
+/// Extra `before` edges under which every ordering reaches `destination`, and none blocks.
+#[derive(Debug)]
+struct Determinization {
+    edges: Vec<[EventInstance; 2]>,
+    destination: Situation,
+}
+/// The situations a `Task::Query` runs against.
+#[derive(Debug, Serialize, Deserialize)]
+enum QueryTarget {
+    Initial,
+    /// Each of the agent's destinations, in turn.
+    AgentDestinations {
+        agent: Agent,
+    },
+    GlobalDestinations,
+}
+/// How many of the destinations of an event graph a fact must hold in.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+enum Modality {
+    /// Holds in every destination.
+    Necessary,
+    /// Holds in some destination.
+    Possible,
+    /// Holds in some destination, but not all.
+    Contested,
+}
+/// Dense set of indices in `0..len`, for a `len` fixed on creation or by `resize`.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+struct IndexBits {
+    words: Vec<u64>,
+}
+/// Over-approximates the facts an event may read (in its guards and `terminate` queries),
+/// and may write (by terminating or creating them).
+struct Footprint {
+    reads: Vec<FactPattern>,
+    writes: Vec<FactPattern>,
+}
+/// Walks the linear extensions of an `EventGraph`'s order, as a tree of shared prefixes.
+/// Events are identified by their position in `events`.
+/// Orderings differing only in the order of adjacent independent events
+/// (i.e. in the same Mazurkiewicz trace) reach the same situation, so only
+/// one representative per trace is explored, using sleep sets.
+/// Likewise, orderings differing only in the order of symmetric event instances
+/// are equivalent, so only one representative per multiset permutation is explored.
+struct LinearExtensions<'a> {
+    events: Vec<&'a EventInstance>,
+    preds: Vec<IndexBits>,
+    dependent: Vec<IndexBits>,
+    /// Maps each event to the previous event in `events` that is symmetric to it, if any.
+    /// Symmetric instances have equal `event`s, and equal order relations to all other events.
+    /// They are only explored in the order they appear in `events`.
+    prev_symmetric: Vec<Option<usize>>,
+    /// Maps each visited pair of done events and situation to the sleep set it was explored with.
+    visited: HashMap<(IndexBits, Situation), IndexBits>,
+    ordering: Vec<usize>,
+    outcomes: Outcomes,
 }
+/// Walks the orderings of a `ClosedOrder`'s events, swapping each unordered dependent pair
+/// wherever the two are adjacent. Events are identified by their position in `events`.
+struct Transpositions<'a> {
+    closed_order: &'a ClosedOrder,
+    preds: Vec<IndexBits>,
+    pairs: Vec<[usize; 2]>,
+    visited: HashSet<(IndexBits, Situation)>,
+    /// Maps done events and two situations to the destinations each then reaches,
+    /// by each ordering of the remaining events under which neither blocks.
+    continuations: HashMap<(IndexBits, [Situation; 2]), BTreeSet<[Situation; 2]>>,
+    /// Maps each pair to the destinations reached with it swapped each way, and otherwise
+    /// ordered alike.
+    swapped: BTreeMap<[usize; 2], BTreeSet<[Situation; 2]>>,
+}
+/// The transitive closure of the `before` relation of an `EventGraph`, between its `happen` events.
+/// Events are identified by their position in `events`.
+#[derive(Clone)]
 struct ClosedOrder {
-    before: HashSet<[EventInstance; 2]>,
+    events: Vec<EventInstance>,
+    index_of: HashMap<EventInstance, usize>,
+    /// `after[a]` contains `b` iff `events[a]` is transitively before `events[b]`.
+    after: Vec<IndexBits>,
 }
 #[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
 struct EventGraph {
@@ -56,121 +192,637 @@ struct PartialEventGraph {
     depend: HashSet<EventInstance>,
     event_graph: EventGraph,
 }
-#[derive(Clone, Default, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[derive(Clone, Default, Eq, PartialEq, Hash, Ord, PartialOrd, Serialize, Deserialize)]
+#[serde(from = "SituationRepr", into = "SituationRepr")]
 struct Situation {
     truth: BTreeMap<Fact, bool>,
 }
+/// How a `Situation` is written: its facts, split by truth value.
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "Situation")]
+struct SituationRepr {
+    #[serde(rename = "true", default)]
+    true_facts: Vec<Fact>,
+    #[serde(rename = "false", default)]
+    false_facts: Vec<Fact>,
+}
+/// Written as `kind(field: value, ..)`, naming the kind and fields as the schema does.
 #[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd)]
 struct Fact {
-    pub bits: u32,
+    pub bits: FactBits,
 }
+/// Wide enough for a kind tag and fields over domains of billions of values.
+type FactBits = u128;
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
 struct FactPattern {
     fact: Fact,
-    mask: u32,
+    mask: FactBits,
+}
+/// Why `Fact::try_pack` failed.
+#[derive(Debug)]
+enum PackError {
+    UnknownKind { kind_idx: u8 },
+    WrongFieldCount { kind_name: String, expected: usize, found: usize },
+    Overflow { kind_name: String, field_name: String, value: u32, domain: u32 },
 }
 fn pair_copy<A: Copy, B: Copy>((&a, &b): (&A, &B)) -> (A, B) {
     (a, b)
 }
-#[derive(Enum, Copy, Clone, Debug, Serialize, Deserialize)]
-enum Agent {
-    Amy,
-    Bob,
-    Dan,
-}
+/// Agents are created, renamed and removed at runtime, and identified by name.
+type Agent = String;
 #[derive(Debug, Serialize, Deserialize)]
 enum Task {
-    AgentHistoryAdd { agent: Agent, graph: EventGraph },
-    AgentHistoryPrint { agent: Agent },
-    AgentDestinationsPrint { agent: Agent },
+    AgentHistoryAdd {
+        agent: Agent,
+        graph: EventGraph,
+    },
+    AgentHistoryPrint {
+        agent: Agent,
+    },
+    AgentDestinationsPrint {
+        agent: Agent,
+    },
     GlobalHistoryPrint,
     GlobalDestinationsPrint,
+    SchemaPrint,
+    AgentHistoryReduce {
+        agent: Agent,
+    },
+    AgentHassePrint {
+        agent: Agent,
+    },
+    GlobalHassePrint,
+    AgentHistoryDotWrite {
+        agent: Agent,
+        path: String,
+    },
+    GlobalHistoryDotWrite {
+        path: String,
+    },
+    AgentDestinationsDotWrite {
+        agent: Agent,
+        path: String,
+    },
+    GlobalDestinationsDotWrite {
+        path: String,
+    },
+    AgentCreate {
+        agent: Agent,
+    },
+    AgentRename {
+        agent: Agent,
+        new_name: Agent,
+    },
+    AgentRemove {
+        agent: Agent,
+    },
+    AgentsPrint,
+    Save {
+        path: String,
+    },
+    Load {
+        path: String,
+    },
+    /// Saves to `path` after every task that changes the state, until set to `None`.
+    Autosave {
+        path: Option<String>,
+    },
+    /// Prints the shape of each task, and the registered events and agents.
+    Help,
+    /// Replaces the initial situation with one where exactly `facts` hold.
+    InitialSituationSet {
+        facts: Vec<Fact>,
+    },
+    /// Additionally makes `facts` hold in the initial situation.
+    InitialSituationExtend {
+        facts: Vec<Fact>,
+    },
+    InitialSituationPrint,
+    /// Prints the facts differing across the agent's destinations, and the unordered
+    /// pairs of events deciding them.
+    AgentDivergencePrint {
+        agent: Agent,
+    },
+    GlobalDivergencePrint,
+    /// Prints minimal sets of extra `before` edges, of at most `max_edges` edges, that
+    /// each make the agent's destination unique, grouped by that destination.
+    AgentDeterminizePrint {
+        agent: Agent,
+        max_edges: usize,
+    },
+    GlobalDeterminizePrint {
+        max_edges: usize,
+    },
+    /// Prints the facts holding in the agent's destinations as `modality` requires,
+    /// restricted to those matching `filter` if given.
+    AgentModalFactsPrint {
+        agent: Agent,
+        modality: Modality,
+        filter: Option<FactPattern>,
+    },
+    GlobalModalFactsPrint {
+        modality: Modality,
+        filter: Option<FactPattern>,
+    },
+    /// Prints the facts of the target situations matching `pattern`, written like
+    /// `friend(a: 1, b: _)` or `owner(_)`.
+    Query {
+        pattern: String,
+        target: QueryTarget,
+    },
+    /// Creates the partial graph `name`, whose events may depend on events it lacks.
+    PartialCreate {
+        name: String,
+        partial: PartialEventGraph,
+    },
+    PartialCompose {
+        name: String,
+        partial: PartialEventGraph,
+    },
+    PartialPrint {
+        name: String,
+    },
+    /// Prints the dependencies of the partial graph that are not among its events.
+    PartialUnmetPrint {
+        name: String,
+    },
+    /// Adds the unmet dependencies found in the global history, with everything before them.
+    PartialResolve {
+        name: String,
+    },
+    /// Fails while the partial graph has unmet dependencies.
+    PartialDestinationsPrint {
+        name: String,
+    },
+    /// Prints the events each agent's history lacks, though they are before its events,
+    /// by the global order or by the history's own edges.
+    CausalClosureCheck,
+    /// Adds to the agent's history everything globally before its events.
+    AgentPredecessorsImport {
+        agent: Agent,
+    },
+    /// Prints what the agents' histories share, and what each has that the other lacks.
+    AgentsCompare {
+        agent: Agent,
+        other: Agent,
+    },
+    PartialsCompare {
+        name: String,
+        other: String,
+    },
+    /// Adds `event` to the agent's history, after every event the history has,
+    /// under an index that no history or partial graph uses yet.
+    AgentEventRecord {
+        agent: Agent,
+        event: Event,
+    },
+}
+/// Besides the join given by `Compose`, the operations comparing histories.
+trait Lattice {
+    /// The events both have, ordered where both order them.
+    fn meet(&self, rhs: &Self) -> Self;
+    /// What this has that `rhs` lacks.
+    fn difference(&self, rhs: &Self) -> Self;
+    /// Every event of this is in `rhs`, and `rhs` orders them at least as this does.
+    fn is_sub_history_of(&self, rhs: &Self) -> bool;
+    /// A sub-history of `rhs` that has every event `rhs` orders before its events, and
+    /// orders its events just as `rhs` does.
+    fn is_prefix_of(&self, rhs: &Self) -> bool;
 }
 trait Compose<T> {
-    fn compose(&mut self, rhs: &T);
-    fn composed(mut self, rhs: &T) -> Self
-    where
-        Self: Sized,
-    {
-        self.compose(rhs);
-        self
-    }
+    type Error;
+    /// Leaves `self` unchanged if an error is returned.
+    fn compose(&mut self, rhs: &T) -> Result<(), Self::Error>;
+}
+/// A sequence of events, each before the next, and the last before the first.
+#[derive(Debug)]
+struct OrderCycle {
+    cycle: Vec<EventInstance>,
+}
+/// An index naming each of `events`, which must be named by distinct indices.
+#[derive(Debug)]
+struct IndexClash {
+    index: u32,
+    events: Vec<Event>,
+}
+/// Why `Compose::compose` refused to compose two event graphs.
+#[derive(Debug)]
+enum ComposeError {
+    Cycle(OrderCycle),
+    IndexClashes(Vec<IndexClash>),
+}
+/// Agent histories whose orders are acyclic alone, but together form `cycle`.
+/// Each `before` edge of the cycle is listed with the agents whose histories contain it.
+#[derive(Debug)]
+struct GlobalConflict {
+    cycle: Vec<EventInstance>,
+    edge_agents: Vec<([EventInstance; 2], Vec<Agent>)>,
+}
+#[derive(Debug)]
+enum TaskError {
+    UnknownEvent {
+        event: Event,
+    },
+    UnknownAgent {
+        agent: Agent,
+    },
+    AgentExists {
+        agent: Agent,
+    },
+    Io(std::io::Error),
+    Ron(ron::Error),
+    Cycle(OrderCycle),
+    Compose(ComposeError),
+    GlobalConflict(GlobalConflict),
+    UnknownKind {
+        kind: String,
+    },
+    UnknownField {
+        kind: String,
+        field: String,
+    },
+    PatternSyntax {
+        pattern: String,
+    },
+    Pack(PackError),
+    /// Some argument is outside the domain of a field it is given to.
+    ArgOutOfDomain {
+        event: Event,
+    },
+    UnknownPartial {
+        name: String,
+    },
+    PartialExists {
+        name: String,
+    },
+    /// The destinations of a partial graph would ignore the effects of its unmet dependencies.
+    Incomplete {
+        unmet: Vec<EventInstance>,
+    },
+    /// Every index is in use, or was handed out before.
+    IndicesExhausted,
+}
+/// Reads `Task`s from RON text, one at a time, each possibly spanning multiple lines.
+struct TaskReader<R> {
+    reader: R,
+    /// Prompts for each line on stdout, if set.
+    interactive: bool,
+    lines_read: usize,
+    /// The line at which the last task returned began.
+    first_line: usize,
+}
+/// The text of a task that failed to parse, which started at line `first_line` of the input.
+#[derive(Debug)]
+struct TaskParseError {
+    error: ron::Error,
+    text: String,
+    first_line: usize,
+}
+/// Why `TaskReader::next_task` failed.
+#[derive(Debug)]
+enum TaskReadError {
+    Io(std::io::Error),
+    Parse(TaskParseError),
 }
+#[derive(Serialize, Deserialize)]
 struct ReplState {
     initial_situation: Situation,
-    agent_histories: EnumMap<Agent, EventGraph>,
+    agent_histories: BTreeMap<Agent, EventGraph>,
+    #[serde(default)]
+    partial_graphs: BTreeMap<String, PartialEventGraph>,
+    /// No index below this is handed out again by `allocate_index`.
+    #[serde(default)]
+    next_index: u32,
+    #[serde(skip)]
+    autosave: Option<String>,
 }
 
 //////////////////////////////////////////////////////
 
+const fn bits_len_for(domain: u32) -> u8 {
+    (u32::BITS - domain.saturating_sub(1).leading_zeros()) as u8
+}
+fn schema() -> &'static Schema {
+    SCHEMA.get().expect("schema not installed")
+}
+impl FactFieldMeta {
+    const fn bits_len(&self) -> u8 {
+        bits_len_for(self.domain)
+    }
+}
+impl FactKindMeta {
+    fn bits_len(&self) -> u32 {
+        self.field_metas.iter().map(|field_meta| field_meta.bits_len() as u32).sum()
+    }
+    /// Pairs each field with the bits it occupies, given that the fields start at `offset`.
+    fn field_ranges(
+        &self,
+        mut offset: u8,
+    ) -> impl Iterator<Item = (&FactFieldMeta, Range<u8>)> + '_ {
+        self.field_metas.iter().map(move |field_meta| {
+            let new_offset = offset + field_meta.bits_len();
+            let range = offset..new_offset;
+            offset = new_offset;
+            (field_meta, range)
+        })
+    }
+    fn field_meta(&self, field_name: &str) -> Option<&FactFieldMeta> {
+        self.field_metas.iter().find(|field_meta| field_meta.field_name == field_name)
+    }
+}
+impl FactTemplate {
+    fn check(
+        &self,
+        schema: &Schema,
+        event_def: &EventDef,
+        create: bool,
+    ) -> Result<(), SchemaError> {
+        let event_name = || event_def.event_name.clone();
+        let kind_name = || self.kind_name.clone();
+        let fkm = match schema.kind_idx(&self.kind_name) {
+            Some(kind_idx) => &schema.kind_metas[kind_idx as usize],
+            None => {
+                return Err(SchemaError::UnknownKind {
+                    event_name: event_name(),
+                    kind_name: kind_name(),
+                })
+            }
+        };
+        for (field_name, term) in self.fields.iter() {
+            if fkm.field_meta(field_name).is_none() {
+                let field_name = field_name.clone();
+                return Err(SchemaError::UnknownField {
+                    event_name: event_name(),
+                    kind_name: kind_name(),
+                    field_name,
+                });
+            }
+            if let Term::Param(param) = term {
+                if event_def.param_idx(param).is_none() {
+                    return Err(SchemaError::UnknownParam {
+                        event_name: event_name(),
+                        param: param.clone(),
+                    });
+                }
+            }
+        }
+        if create {
+            if let Some(field_meta) = fkm
+                .field_metas
+                .iter()
+                .find(|field_meta| !self.fields.contains_key(&field_meta.field_name))
+            {
+                let field_name = field_meta.field_name.clone();
+                return Err(SchemaError::UnboundField {
+                    event_name: event_name(),
+                    kind_name: kind_name(),
+                    field_name,
+                });
+            }
+        }
+        Ok(())
+    }
+    /// Substitutes `args` for the params of `event_def`. Assumes `args` has the right arity.
+    /// Returns None if some field would be given a value outside its domain.
+    fn instantiate(&self, event_def: &EventDef, args: &[u32]) -> Option<FactPattern> {
+        let schema = schema();
+        let kind_idx = schema.kind_idx(&self.kind_name)?;
+        let fkm = &schema.kind_metas[kind_idx as usize];
+        let mut fact_pattern = FactPattern::of_kind(kind_idx);
+        for (field_meta, bit_range) in fkm.field_ranges(schema.kind_bits_len()) {
+            let value = match self.fields.get(&field_meta.field_name) {
+                None => continue,
+                Some(Term::Value(value)) => *value,
+                Some(Term::Param(param)) => args[event_def.param_idx(param)?],
+            };
+            if value >= field_meta.domain {
+                return None;
+            }
+            fact_pattern = fact_pattern.with(FactPattern::from_bit_slice(value, bit_range));
+        }
+        Some(fact_pattern)
+    }
+}
+impl EventDef {
+    fn param_idx(&self, param: &str) -> Option<usize> {
+        self.params.iter().position(|p| p == param)
+    }
+}
+impl Event {
+    /// Returns the definition of this event, if the schema has one with a matching arity.
+    fn def(&self) -> Option<&'static EventDef> {
+        schema().event_def(&self.name).filter(|event_def| event_def.params.len() == self.args.len())
+    }
+    fn footprint(&self) -> Option<Footprint> {
+        let event_def = self.def()?;
+        let instantiate_all = |templates: &[FactTemplate]| {
+            templates
+                .iter()
+                .map(|template| template.instantiate(event_def, &self.args))
+                .collect::<Option<Vec<_>>>()
+        };
+        let terminate = instantiate_all(&event_def.terminate)?;
+        let mut reads = instantiate_all(&event_def.require)?;
+        reads.extend(instantiate_all(&event_def.forbid)?);
+        reads.extend(terminate.iter().copied());
+        let mut writes = terminate;
+        writes.extend(instantiate_all(&event_def.create)?);
+        Some(Footprint { reads, writes })
+    }
+}
+impl Footprint {
+    /// Two events are independent if neither writes a fact the other reads or writes.
+    /// Then, occurring in either order has the same effect, and neither can block the other.
+    fn independent_of(&self, rhs: &Self) -> bool {
+        let touches = |writes: &[FactPattern], fp: &Self| {
+            writes.iter().any(|&w| fp.reads.iter().chain(fp.writes.iter()).any(|&p| w.overlaps(p)))
+        };
+        !touches(&self.writes, rhs) && !touches(&rhs.writes, self)
+    }
+}
+impl Schema {
+    fn from_ron_str(s: &str) -> Result<Self, SchemaError> {
+        let me: Self = ron::de::from_str(s).map_err(SchemaError::Parse)?;
+        if me.kind_metas.len() > u8::MAX as usize + 1 {
+            return Err(SchemaError::TooManyKinds { kinds_len: me.kind_metas.len() });
+        }
+        for fkm in me.kind_metas.iter() {
+            // no fact of the kind could be packed
+            if let Some(field_meta) = fkm.field_metas.iter().find(|fm| fm.domain == 0) {
+                return Err(SchemaError::EmptyDomain {
+                    kind_name: fkm.kind_name.clone(),
+                    field_name: field_meta.field_name.clone(),
+                });
+            }
+            let bits_len = me.kind_bits_len() as u32 + fkm.bits_len();
+            if bits_len > FactBits::BITS {
+                return Err(SchemaError::FactTooWide {
+                    kind_name: fkm.kind_name.clone(),
+                    bits_len,
+                });
+            }
+        }
+        for (i, event_def) in me.event_defs.iter().enumerate() {
+            if me.event_defs[..i].iter().any(|prev| prev.event_name == event_def.event_name) {
+                return Err(SchemaError::DuplicateEvent {
+                    event_name: event_def.event_name.clone(),
+                });
+            }
+            for template in
+                event_def.require.iter().chain(&event_def.forbid).chain(&event_def.terminate)
+            {
+                template.check(&me, event_def, false)?;
+            }
+            for template in event_def.create.iter() {
+                template.check(&me, event_def, true)?;
+            }
+        }
+        Ok(me)
+    }
+    fn from_path(path: &str) -> Result<Self, SchemaError> {
+        Self::from_ron_str(&std::fs::read_to_string(path).map_err(SchemaError::Io)?)
+    }
+    /// Makes this the schema used by `Fact::try_pack`, `FactHr` etc. for the rest of the process.
+    fn install(self) {
+        if SCHEMA.set(self).is_err() {
+            panic!("schema already installed");
+        }
+    }
+    fn kind_bits_len(&self) -> u8 {
+        bits_len_for(self.kind_metas.len() as u32)
+    }
+    fn kind_idx(&self, kind_name: &str) -> Option<u8> {
+        self.kind_metas.iter().position(|fkm| fkm.kind_name == kind_name).map(|idx| idx as u8)
+    }
+    fn event_def(&self, event_name: &str) -> Option<&EventDef> {
+        self.event_defs.iter().find(|event_def| event_def.event_name == event_name)
+    }
+}
 impl Debug for FactHr {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        let fkm = KIND_METAS[self.0.read(0..KIND_BITS_LEN) as usize];
+        let schema = schema();
+        let kind_bits_len = schema.kind_bits_len();
+        let fkm = match schema.kind_metas.get(self.0.read(0..kind_bits_len) as usize) {
+            Some(fkm) => fkm,
+            None => return f.debug_tuple("FactHr").field(&self.0.bits).finish(),
+        };
         let mut ds = f.debug_struct("FactHr");
-        let mut offset = KIND_BITS_LEN;
         ds.field("kind", &fkm.kind_name);
-        for field_meta in fkm.field_metas {
-            let new_offset = offset + field_meta.bits_len;
-            ds.field(field_meta.field_name, &self.0.read(offset..new_offset));
-            offset = new_offset;
+        for (field_meta, bit_range) in fkm.field_ranges(kind_bits_len) {
+            ds.field(&field_meta.field_name, &self.0.read(bit_range));
         }
         ds.finish()
     }
 }
-impl Fact {
-    fn pack(kind_idx: u8, field_bits: &[u32]) -> Self {
-        let fkm = KIND_METAS[kind_idx as usize];
-        let mut fact = Self::default();
-        fact.write(FactPattern::from_bit_slice(kind_idx as u32, 0..KIND_BITS_LEN));
-        let mut offset = KIND_BITS_LEN;
-        for (&field_bits, field_meta) in field_bits.iter().zip(fkm.field_metas) {
-            let new_offset = offset + field_meta.bits_len;
-            fact.write(FactPattern::from_bit_slice(field_bits as u32, offset..new_offset));
-            offset = new_offset;
+impl std::fmt::Display for FactHr {
+    /// Writes e.g. `friend(a: 1, b: 2)`, as facts are written in tasks.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let schema = schema();
+        let kind_bits_len = schema.kind_bits_len();
+        let fkm = match schema.kind_metas.get(self.0.read(0..kind_bits_len) as usize) {
+            Some(fkm) => fkm,
+            None => return write!(f, "{:#b}", self.0.bits),
+        };
+        write!(f, "{}(", fkm.kind_name)?;
+        for (i, (field_meta, bit_range)) in fkm.field_ranges(kind_bits_len).enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", field_meta.field_name, self.0.read(bit_range))?;
         }
-        fact
+        write!(f, ")")
     }
 }
-impl<'a, T> HeapPermute<'a, T> {
-    /// https://en.wikipedia.org/wiki/Heap%27s_algorithm
-    fn new(arr: &'a mut [T]) -> Self {
-        assert!(arr.len() < usize::MAX);
-        Self { n: usize::MAX, c: std::iter::repeat(0).take(arr.len()).collect(), i: 0, arr }
-    }
-    fn next(&mut self) -> Option<&[T]> {
-        if self.n == usize::MAX {
-            self.n = self.arr.len();
-            return Some(self.arr);
+/// Escapes `s` for use in a quoted DOT string, with each line left-justified.
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\l")
+}
+/// Renders each destination as a node listing its true facts, reached from the
+/// initial situation by an edge labelled with its witness ordering, in Graphviz DOT.
+fn destinations_to_dot(destinations: &BTreeMap<Situation, Vec<EventInstance>>) -> String {
+    use std::fmt::Write;
+    let mut dot = String::from("digraph Destinations {\n    node [shape=box];\n");
+    writeln!(dot, "    initial [shape=ellipse];").unwrap();
+    for (i, (situation, ordering)) in destinations.iter().enumerate() {
+        let mut label = String::new();
+        for (&fact, _value) in situation.truth.iter().filter(|(_fact, &value)| value) {
+            writeln!(label, "{}", FactHr(fact)).unwrap();
         }
-        while self.i < self.n {
-            if self.c[self.i] < self.i {
-                if self.i % 2 == 0 {
-                    self.arr.swap(0, self.i);
-                } else {
-                    self.arr.swap(self.c[self.i], self.i);
-                }
-                self.c[self.i] += 1;
-                self.i = 0;
-                return Some(self.arr);
-            } else {
-                self.c[self.i] = 0;
-                self.i += 1;
-            }
+        writeln!(dot, "    d{i} [label=\"{}\"];", dot_escape(&label)).unwrap();
+        let mut witness = String::new();
+        for ei in ordering.iter() {
+            writeln!(witness, "{ei}").unwrap();
         }
-        None
+        writeln!(dot, "    initial -> d{i} [label=\"{}\"];", dot_escape(&witness)).unwrap();
+    }
+    dot.push_str("}\n");
+    dot
+}
+impl Fact {
+    /// Packs the kind tag and `field_values`, given in the order of the kind's fields.
+    fn try_pack(kind_idx: u8, field_values: &[u32]) -> Result<Self, PackError> {
+        let field_values: Vec<_> = field_values.iter().copied().map(Some).collect();
+        FactPattern::try_of_fields(kind_idx, &field_values).map(|fact_pattern| fact_pattern.fact)
     }
 }
 impl FactPattern {
+    /// Matches facts of the kind, with `field_values` given in the order of its fields,
+    /// and None for fields matching any value.
+    fn try_of_fields(kind_idx: u8, field_values: &[Option<u32>]) -> Result<Self, PackError> {
+        let schema = schema();
+        let fkm =
+            schema.kind_metas.get(kind_idx as usize).ok_or(PackError::UnknownKind { kind_idx })?;
+        if field_values.len() != fkm.field_metas.len() {
+            let (expected, found) = (fkm.field_metas.len(), field_values.len());
+            let kind_name = fkm.kind_name.clone();
+            return Err(PackError::WrongFieldCount { kind_name, expected, found });
+        }
+        let mut fact_pattern = FactPattern::of_kind(kind_idx);
+        for (&value, (field_meta, bit_range)) in
+            field_values.iter().zip(fkm.field_ranges(schema.kind_bits_len()))
+        {
+            let value = match value {
+                Some(value) => value,
+                None => continue,
+            };
+            if value >= field_meta.domain {
+                return Err(PackError::Overflow {
+                    kind_name: fkm.kind_name.clone(),
+                    field_name: field_meta.field_name.clone(),
+                    value,
+                    domain: field_meta.domain,
+                });
+            }
+            fact_pattern = fact_pattern.with(FactPattern::from_bit_slice(value, bit_range));
+        }
+        Ok(fact_pattern)
+    }
     fn from_bit_slice(bits: u32, bit_range: Range<u8>) -> Self {
         let mask = bit_mask(range_copy(&bit_range));
         // println!("mask {:b}", mask);
-        Self { fact: Fact { bits: (bits << bit_range.start) & mask }, mask }
+        Self { fact: Fact { bits: shl_or_zero(bits as FactBits, bit_range.start) & mask }, mask }
+    }
+    fn of_kind(kind_idx: u8) -> Self {
+        Self::from_bit_slice(kind_idx as u32, 0..schema().kind_bits_len())
+    }
+    /// Returns true if some fact matches both patterns.
+    const fn overlaps(self, rhs: Self) -> bool {
+        (self.fact.bits ^ rhs.fact.bits) & self.mask & rhs.mask == 0
+    }
+    /// Additionally constrains the bits in the mask of `rhs`.
+    fn with(self, rhs: Self) -> Self {
+        Self { fact: self.fact.with(rhs), mask: self.mask | rhs.mask }
     }
 }
-const fn bit_mask(range: Range<u8>) -> u32 {
-    let from_start = !0 << range.start;
-    let to_end = !0 << range.end;
-    !(from_start & to_end)
+const fn bit_mask(range: Range<u8>) -> FactBits {
+    shl_or_zero(!0, range.start) & !shl_or_zero(!0, range.end)
+}
+/// Like `bits << shift`, but shifting out every bit instead of overflowing.
+const fn shl_or_zero(bits: FactBits, shift: u8) -> FactBits {
+    match bits.checked_shl(shift as u32) {
+        Some(bits) => bits,
+        None => 0,
+    }
 }
 const fn range_copy(range: &Range<u8>) -> Range<u8> {
     range.start..range.end
@@ -193,211 +845,2356 @@ impl Situation {
             .filter(move |(fact, _value)| fact.matches_pattern(fact_pattern))
             .map(pair_copy)
     }
-    pub fn try_delta(&self, event: Event) -> Option<Self> {
+    fn holds(&self, fact: Fact) -> bool {
+        self.truth.get(&fact) == Some(&true)
+    }
+    fn holds_any(&self, fact_pattern: FactPattern) -> bool {
+        self.query(fact_pattern).any(|(_fact, value)| value)
+    }
+    /// Returns None if `event` is not defined, or its guards reject this situation.
+    pub fn try_delta(&self, event: &Event) -> Option<Self> {
         let mut delta = Situation::default();
         // println!("delta for event {:?}", event);
-        match event {
-            Event::SetOwner { owner } => {
-                delta.truth.extend(
-                    self.query(FactPattern::from_bit_slice(0b0, 0..1))
-                        .map(|(fact, _value)| (fact, false)),
-                );
-                delta.insert(Fact::pack(0, &[owner]), true);
+        let event_def = event.def()?;
+        for template in event_def.require.iter() {
+            if !self.holds_any(template.instantiate(event_def, &event.args)?) {
+                return None;
             }
-            Event::BecomeFriends { a, b } => {
-                delta.insert(Fact::pack(1, &[a, b]), true);
+        }
+        for template in event_def.forbid.iter() {
+            if self.holds_any(template.instantiate(event_def, &event.args)?) {
+                return None;
             }
         }
+        for template in event_def.terminate.iter() {
+            let fact_pattern = template.instantiate(event_def, &event.args)?;
+            delta.truth.extend(self.query(fact_pattern).map(|(fact, _value)| (fact, false)));
+        }
+        for template in event_def.create.iter() {
+            delta.insert(template.instantiate(event_def, &event.args)?.fact, true);
+        }
         Some(delta)
     }
 }
-impl ClosedOrder {
-    fn respected_by(&self, arr: &[EventInstance]) -> bool {
-        for window in arr.windows(2) {
-            if let &[a, b] = window {
-                if self.before.contains(&[b, a]) {
-                    return false;
-                }
-            }
+impl IndexBits {
+    fn new(len: usize) -> Self {
+        Self { words: vec![0; len.div_ceil(64)] }
+    }
+    fn contains(&self, idx: usize) -> bool {
+        self.words[idx / 64] & (1 << (idx % 64)) != 0
+    }
+    fn insert(&mut self, idx: usize) {
+        self.words[idx / 64] |= 1 << (idx % 64);
+    }
+    fn remove(&mut self, idx: usize) {
+        self.words[idx / 64] &= !(1 << (idx % 64));
+    }
+    fn is_subset(&self, rhs: &Self) -> bool {
+        self.words.iter().zip(rhs.words.iter()).all(|(&l, &r)| l & !r == 0)
+    }
+    fn intersect_with(&mut self, rhs: &Self) {
+        for (l, &r) in self.words.iter_mut().zip(rhs.words.iter()) {
+            *l &= r;
         }
-        true
     }
-    fn take_cycle(&self, happen: &HashSet<EventInstance>) -> Option<EventInstance> {
-        happen.iter().copied().find(|&x| self.before.contains(&[x, x]))
+    fn difference_with(&mut self, rhs: &Self) {
+        for (l, &r) in self.words.iter_mut().zip(rhs.words.iter()) {
+            *l &= !r;
+        }
     }
-}
-impl Compose<Self> for EventGraph {
-    fn compose(&mut self, rhs: &Self) {
-        self.happen.extend(rhs.happen.iter().copied());
-        self.before.extend(rhs.before.iter().copied());
+    fn union_with(&mut self, rhs: &Self) {
+        for (l, &r) in self.words.iter_mut().zip(rhs.words.iter()) {
+            *l |= r;
+        }
+    }
+    fn resize(&mut self, len: usize) {
+        self.words.resize(len.div_ceil(64), 0);
+    }
+    fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words.iter().enumerate().flat_map(|(word_idx, &word)| {
+            (0..64).filter(move |bit| word & (1 << bit) != 0).map(move |bit| word_idx * 64 + bit)
+        })
     }
 }
-impl EventGraph {
-    fn closed_before(&self) -> ClosedOrder {
-        ClosedOrder { before: Self::transitively_close_before(&self.happen, self.before.clone()) }
+impl<'a> LinearExtensions<'a> {
+    fn new(closed_order: &'a ClosedOrder) -> Self {
+        let events: Vec<_> = closed_order.events.iter().collect();
+        let succs = &closed_order.after;
+        let mut preds = vec![IndexBits::new(events.len()); events.len()];
+        for (idx_a, succs_a) in succs.iter().enumerate() {
+            for idx_b in succs_a.iter() {
+                preds[idx_b].insert(idx_a);
+            }
+        }
+        // instances of the same event are adjacent in `events`, because they are sorted
+        let prev_symmetric = (0..events.len())
+            .map(|idx| {
+                (0..idx)
+                    .rev()
+                    .take_while(|&prev| events[prev].event == events[idx].event)
+                    .find(|&prev| preds[prev] == preds[idx] && succs[prev] == succs[idx])
+            })
+            .collect();
+        // undefined events have no footprint; consider them dependent on everything
+        let footprints: Vec<_> = events.iter().map(|ei| ei.event.footprint()).collect();
+        let mut dependent = vec![IndexBits::new(events.len()); events.len()];
+        for (idx_a, fp_a) in footprints.iter().enumerate() {
+            for (idx_b, fp_b) in footprints.iter().enumerate() {
+                let independent = match (fp_a, fp_b) {
+                    (Some(fp_a), Some(fp_b)) => idx_a != idx_b && fp_a.independent_of(fp_b),
+                    _ => false,
+                };
+                if !independent {
+                    dependent[idx_a].insert(idx_b);
+                }
+            }
+        }
+        Self {
+            events,
+            preds,
+            dependent,
+            prev_symmetric,
+            visited: Default::default(),
+            ordering: vec![],
+            outcomes: Default::default(),
+        }
     }
-    fn destinations(
-        &self,
-        initial_situation: &Situation,
-    ) -> BTreeMap<Situation, Vec<EventInstance>> {
-        let mut eq_classes = BTreeMap::<Situation, Vec<EventInstance>>::default();
-        let closed_before = self.closed_before();
-        let mut arr: Vec<_> = self.happen.iter().copied().collect();
-        let mut hp = HeapPermute::new(&mut arr);
-        while let Some(arr) = hp.next() {
-            if closed_before.respected_by(arr) {
-                let mut sit = initial_situation.clone();
-                for ei in arr {
-                    let delta = sit.try_delta(ei.event).unwrap();
-                    sit.update(&delta);
-                }
-                if !eq_classes.contains_key(&sit) {
-                    eq_classes.insert(sit, arr.to_vec());
-                }
-            }
-        }
-        eq_classes
-    }
-    fn transitively_close_before(
-        happen: &HashSet<EventInstance>,
-        mut before: HashSet<[EventInstance; 2]>,
-    ) -> HashSet<[EventInstance; 2]> {
-        'outer: loop {
-            for &[from, via] in before.iter() {
-                if !happen.contains(&from) || !happen.contains(&via) {
-                    continue;
-                }
-                for &to in happen.iter() {
-                    if before.contains(&[via, to]) && !before.contains(&[from, to]) {
-                        before.insert([from, to]);
-                        continue 'outer;
-                    }
+    /// Visits every ordering extending `self.ordering`, whose events are `done`,
+    /// except for those starting with an event in `sleep`, as such orderings are
+    /// equivalent to orderings visited elsewhere.
+    /// Each pair of `done` and `sit` is visited once, as its extensions are the same
+    /// however it was reached, unless it is reached with a sleep set that isn't a
+    /// superset of the one it was explored with.
+    fn explore(&mut self, done: &mut IndexBits, sit: &Situation, mut sleep: IndexBits) {
+        match self.visited.get_mut(&(done.clone(), sit.clone())) {
+            Some(visited_sleep) if visited_sleep.is_subset(&sleep) => return,
+            Some(visited_sleep) => {
+                sleep.intersect_with(visited_sleep);
+                *visited_sleep = sleep.clone();
+            }
+            None => {
+                self.visited.insert((done.clone(), sit.clone()), sleep.clone());
+            }
+        }
+        if self.ordering.len() == self.events.len() {
+            let ordering = &self.ordering;
+            let events = &self.events;
+            self.outcomes
+                .destinations
+                .entry(sit.clone())
+                .or_insert_with(|| ordering.iter().map(|&idx| events[idx].clone()).collect());
+            return;
+        }
+        for idx in 0..self.events.len() {
+            if done.contains(idx) || sleep.contains(idx) || !self.preds[idx].is_subset(done) {
+                continue;
+            }
+            if self.prev_symmetric[idx].is_some_and(|prev| !done.contains(prev)) {
+                continue;
+            }
+            match sit.try_delta(&self.events[idx].event) {
+                Some(delta) => {
+                    let mut next_sit = sit.clone();
+                    next_sit.update(&delta);
+                    let mut next_sleep = sleep.clone();
+                    next_sleep.difference_with(&self.dependent[idx]);
+                    done.insert(idx);
+                    self.ordering.push(idx);
+                    self.explore(done, &next_sit, next_sleep);
+                    self.ordering.pop();
+                    done.remove(idx);
                 }
+                None => self.outcomes.add_blocked(Blocked {
+                    ordering: self.ordering.iter().map(|&idx| self.events[idx].clone()).collect(),
+                    event_instance: self.events[idx].clone(),
+                    situation: sit.clone(),
+                }),
             }
-            break before;
+            sleep.insert(idx);
         }
     }
 }
-impl Debug for Situation {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        struct Filtered<'a>(&'a Situation, bool);
-        impl Debug for Filtered<'_> {
-            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-                let iter = self
-                    .0
-                    .truth
-                    .iter()
-                    .filter(|&(_fact, &value)| value == self.1)
-                    .map(|(&fact, _value)| FactHr(fact));
-                f.debug_set().entries(iter).finish()
+impl<'a> Transpositions<'a> {
+    fn new(closed_order: &'a ClosedOrder) -> Self {
+        let events_len = closed_order.events.len();
+        let mut preds = vec![IndexBits::new(events_len); events_len];
+        for (idx_a, succs_a) in closed_order.after.iter().enumerate() {
+            for idx_b in succs_a.iter() {
+                preds[idx_b].insert(idx_a);
             }
         }
-        f.debug_struct("Situation")
-            .field("true", &Filtered(self, true))
-            .field("false", &Filtered(self, false))
-            .finish()
+        Self {
+            closed_order,
+            preds,
+            pairs: closed_order.unordered_dependent_pairs(),
+            visited: Default::default(),
+            continuations: Default::default(),
+            swapped: Default::default(),
+        }
     }
-}
-impl Compose<Self> for PartialEventGraph {
-    fn compose(&mut self, rhs: &Self) {
-        self.depend.extend(rhs.depend.iter().copied());
-        self.event_graph.compose(&rhs.event_graph);
+    /// Returns `sit` after the event at `idx`, unless it is blocked.
+    fn step(&self, sit: &Situation, idx: usize) -> Option<Situation> {
+        let delta = sit.try_delta(&self.closed_order.events[idx].event)?;
+        let mut next_sit = sit.clone();
+        next_sit.update(&delta);
+        Some(next_sit)
     }
-}
-impl PartialEventGraph {
-    fn is_complete(&self) -> bool {
-        self.depend.is_subset(&self.event_graph.happen)
+    fn enabled(&self, done: &IndexBits) -> Vec<usize> {
+        (0..self.preds.len())
+            .filter(|&idx| !done.contains(idx) && self.preds[idx].is_subset(done))
+            .collect()
     }
-}
-impl Fact {
-    pub const fn matches_pattern(self, fact_pattern: FactPattern) -> bool {
-        self.bits & fact_pattern.mask == fact_pattern.fact.bits
+    fn explore(&mut self, done: &mut IndexBits, sit: &Situation) {
+        if !self.visited.insert((done.clone(), sit.clone())) {
+            return;
+        }
+        let enabled = self.enabled(done);
+        for pair_idx in 0..self.pairs.len() {
+            let [a, b] = self.pairs[pair_idx];
+            if !enabled.contains(&a) || !enabled.contains(&b) {
+                continue;
+            }
+            let a_first = self.step(sit, a).and_then(|sit| self.step(&sit, b));
+            let b_first = self.step(sit, b).and_then(|sit| self.step(&sit, a));
+            if let (Some(a_first), Some(b_first)) = (a_first, b_first) {
+                if a_first != b_first {
+                    let mut done = done.clone();
+                    done.insert(a);
+                    done.insert(b);
+                    let destinations = self.continue_both(&done, [a_first, b_first]);
+                    self.swapped.entry([a, b]).or_default().extend(destinations);
+                }
+            }
+        }
+        for idx in enabled {
+            if let Some(next_sit) = self.step(sit, idx) {
+                done.insert(idx);
+                self.explore(done, &next_sit);
+                done.remove(idx);
+            }
+        }
     }
-    pub fn write(&mut self, fact_pattern: FactPattern) {
-        self.bits &= !fact_pattern.mask;
-        self.bits |= fact_pattern.fact.bits;
+    /// Returns the destinations reached from both of `sits`, by each ordering of the
+    /// events not `done` under which neither blocks.
+    fn continue_both(
+        &mut self,
+        done: &IndexBits,
+        sits: [Situation; 2],
+    ) -> BTreeSet<[Situation; 2]> {
+        let key = (done.clone(), sits);
+        if let Some(destinations) = self.continuations.get(&key) {
+            return destinations.clone();
+        }
+        let mut destinations = BTreeSet::new();
+        if done.iter().count() == self.preds.len() {
+            destinations.insert(key.1.clone());
+        }
+        for idx in self.enabled(done) {
+            let [sit_a, sit_b] = &key.1;
+            if let (Some(next_a), Some(next_b)) = (self.step(sit_a, idx), self.step(sit_b, idx)) {
+                let mut next_done = done.clone();
+                next_done.insert(idx);
+                destinations.extend(self.continue_both(&next_done, [next_a, next_b]));
+            }
+        }
+        self.continuations.insert(key, destinations.clone());
+        destinations
     }
-    pub fn with(mut self, fact_pattern: FactPattern) -> Self {
-        self.write(fact_pattern);
-        self
+}
+impl Outcomes {
+    /// Prints each destination with an ordering reaching it, then each blocked ordering.
+    fn print(&self) {
+        println!("{:#?}", self.destinations);
+        for blocked in self.blocked.iter() {
+            println!("blocked: {blocked}");
+        }
     }
-    pub const fn read(self, bit_range: Range<u8>) -> u32 {
-        (self.bits & bit_mask(range_copy(&bit_range))) >> bit_range.start
+    /// Every ordering reaches the same destination, and none blocks.
+    fn is_determined(&self) -> bool {
+        self.destinations.len() == 1 && self.blocked.is_empty()
+    }
+    /// Skips `blocked` if the same event instance was already blocked in the same situation.
+    fn add_blocked(&mut self, blocked: Blocked) {
+        let known = self.blocked.iter().any(|b| {
+            b.event_instance == blocked.event_instance && b.situation == blocked.situation
+        });
+        if !known {
+            self.blocked.push(blocked);
+        }
     }
 }
-impl ReplState {
-    fn handle_task(&mut self, task: Task) {
-        match task {
-            Task::AgentHistoryAdd { agent, graph } => self.agent_histories[agent].compose(&graph),
-            Task::AgentHistoryPrint { agent } => println!("{:#?}", &self.agent_histories[agent]),
-            Task::AgentDestinationsPrint { agent } => {
-                let destinations =
-                    self.agent_histories[agent].destinations(&self.initial_situation);
-                println!("{:#?}", &destinations);
+impl ClosedOrder {
+    /// Closes `before` with Warshall's algorithm, over rows of bits.
+    /// Edges involving events not in `happen` are ignored.
+    fn new(happen: &HashSet<EventInstance>, before: &HashSet<[EventInstance; 2]>) -> Self {
+        let mut events: Vec<_> = happen.iter().cloned().collect();
+        events.sort();
+        let index_of: HashMap<_, _> =
+            events.iter().enumerate().map(|(idx, ei)| (ei.clone(), idx)).collect();
+        let mut after = vec![IndexBits::new(events.len()); events.len()];
+        for [a, b] in before.iter() {
+            if let (Some(&a), Some(&b)) = (index_of.get(a), index_of.get(b)) {
+                after[a].insert(b);
             }
-            Task::GlobalHistoryPrint => {
-                let global = self
-                    .agent_histories
-                    .values()
-                    .fold(EventGraph::default(), |global, local| global.composed(local));
-                println!("{:#?}", &global);
+        }
+        for via in 0..events.len() {
+            let after_via = after[via].clone();
+            for after_from in after.iter_mut() {
+                if after_from.contains(via) {
+                    after_from.union_with(&after_via);
+                }
             }
-            Task::GlobalDestinationsPrint => {
-                let global = self
-                    .agent_histories
-                    .values()
-                    .fold(EventGraph::default(), |global, local| global.composed(local));
-                let destinations = global.destinations(&self.initial_situation);
-                println!("{:#?}", &destinations);
+        }
+        Self { events, index_of, after }
+    }
+    fn contains(&self, [a, b]: &[EventInstance; 2]) -> bool {
+        match (self.index_of.get(a), self.index_of.get(b)) {
+            (Some(&a), Some(&b)) => self.after[a].contains(b),
+            _ => false,
+        }
+    }
+    /// Updates the closure to include the edge `[a, b]`, touching only the rows of
+    /// `a` and its predecessors. Ignored if `a` or `b` is not an event of this order.
+    fn insert_before(&mut self, [a, b]: &[EventInstance; 2]) {
+        let (&a, &b) = match (self.index_of.get(a), self.index_of.get(b)) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return,
+        };
+        if self.after[a].contains(b) {
+            return;
+        }
+        let mut after_b = self.after[b].clone();
+        after_b.insert(b);
+        for (from, after_from) in self.after.iter_mut().enumerate() {
+            if from == a || after_from.contains(a) {
+                after_from.union_with(&after_b);
             }
         }
     }
-}
-
+    /// Adds `ei` to this order, before and after no other event.
+    /// Unlike `new`, this leaves `events` unsorted.
+    fn insert_event(&mut self, ei: &EventInstance) {
+        if self.index_of.contains_key(ei) {
+            return;
+        }
+        self.index_of.insert(ei.clone(), self.events.len());
+        self.events.push(ei.clone());
+        self.after.push(IndexBits::new(0));
+        for after in self.after.iter_mut() {
+            after.resize(self.events.len());
+        }
+    }
+    /// Every `[a, b]` with `a` transitively before `b`.
+    fn edges(&self) -> impl Iterator<Item = [EventInstance; 2]> + '_ {
+        self.after.iter().enumerate().flat_map(move |(a, after_a)| {
+            after_a.iter().map(move |b| [self.events[a].clone(), self.events[b].clone()])
+        })
+    }
+    /// The events with no event after them.
+    fn maximal(&self) -> impl Iterator<Item = &EventInstance> + '_ {
+        self.events
+            .iter()
+            .zip(self.after.iter())
+            .filter(|(_ei, after)| after.iter().next().is_none())
+            .map(|(ei, _after)| ei)
+    }
+    fn take_cycle(&self) -> Option<&EventInstance> {
+        (0..self.events.len())
+            .find(|&idx| self.after[idx].contains(idx))
+            .map(|idx| &self.events[idx])
+    }
+    /// Finds the facts whose truth differs across destinations. An unordered pair of
+    /// dependent events decides a fact if, in some ordering where the two are adjacent,
+    /// swapping them changes the fact's truth in the destination. Orderings blocked
+    /// either way are not compared.
+    fn divergences(&self, initial_situation: &Situation) -> Vec<Divergence> {
+        let truth_values = |destinations: &BTreeMap<Situation, _>, fact| -> BTreeSet<bool> {
+            destinations.keys().map(|situation: &Situation| situation.holds(fact)).collect()
+        };
+        let destinations = self.outcomes(initial_situation).destinations;
+        let facts: BTreeSet<Fact> = destinations
+            .keys()
+            .flat_map(|situation| situation.truth.iter().filter(|(_fact, &value)| value))
+            .map(|(&fact, _value)| fact)
+            .collect();
+        let mut divergences: Vec<_> = facts
+            .into_iter()
+            .filter(|&fact| truth_values(&destinations, fact).len() > 1)
+            .map(|fact| Divergence { fact, deciding_pairs: vec![] })
+            .collect();
+        if divergences.is_empty() {
+            return divergences;
+        }
+        let mut transpositions = Transpositions::new(self);
+        transpositions.explore(&mut IndexBits::new(self.events.len()), initial_situation);
+        for (&[a, b], destinations) in transpositions.swapped.iter() {
+            let pair = [self.events[a].clone(), self.events[b].clone()];
+            for divergence in divergences.iter_mut() {
+                let fact = divergence.fact;
+                if destinations.iter().any(|[sit_a, sit_b]| sit_a.holds(fact) != sit_b.holds(fact))
+                {
+                    divergence.deciding_pairs.push(pair.clone());
+                }
+            }
+        }
+        divergences
+    }
+    /// Returns each pair `[a, b]` of indices, with `a < b`, of events ordered neither way
+    /// whose relative order may matter, because their footprints are dependent.
+    fn unordered_dependent_pairs(&self) -> Vec<[usize; 2]> {
+        let footprints: Vec<_> = self.events.iter().map(|ei| ei.event.footprint()).collect();
+        let mut pairs = vec![];
+        for a in 0..self.events.len() {
+            for b in a + 1..self.events.len() {
+                let independent = match (&footprints[a], &footprints[b]) {
+                    (Some(fp_a), Some(fp_b)) => fp_a.independent_of(fp_b),
+                    _ => false,
+                };
+                if !independent && !self.after[a].contains(b) && !self.after[b].contains(a) {
+                    pairs.push([a, b]);
+                }
+            }
+        }
+        pairs
+    }
+    /// Finds inclusion-minimal sets of at most `max_edges` extra edges, each ordering an
+    /// unordered dependent pair, under which every ordering reaches the same destination,
+    /// and none blocks.
+    /// Every destination reachable by some ordering is forced by some set, unless that
+    /// needs more than `max_edges` edges.
+    fn determinizations(
+        &self,
+        initial_situation: &Situation,
+        max_edges: usize,
+    ) -> Vec<Determinization> {
+        let mut found = vec![];
+        self.determinize_from(initial_situation, max_edges, &mut vec![], &mut found);
+        // drop each edge whose removal still forces the same destination
+        for Determinization { edges, destination } in found.iter_mut() {
+            let mut i = 0;
+            while i < edges.len() {
+                let mut ordered = self.clone();
+                for edge in edges.iter().take(i).chain(edges.iter().skip(i + 1)) {
+                    ordered.insert_before(edge);
+                }
+                let outcomes = ordered.outcomes(initial_situation);
+                if outcomes.is_determined() && outcomes.destinations.contains_key(destination) {
+                    edges.remove(i);
+                } else {
+                    i += 1;
+                }
+            }
+            edges.sort();
+        }
+        found.sort_by(|a, b| (&a.destination, &a.edges).cmp(&(&b.destination, &b.edges)));
+        found.dedup_by(|a, b| (&a.destination, &a.edges) == (&b.destination, &b.edges));
+        found
+    }
+    /// Orders a pair deciding some diverging fact, or else a pair with a blocked event, both
+    /// ways, and recurses on each, until the destination is unique and nothing blocks.
+    /// The two ways partition the orderings between them.
+    fn determinize_from(
+        &self,
+        initial_situation: &Situation,
+        max_edges: usize,
+        edges: &mut Vec<[EventInstance; 2]>,
+        found: &mut Vec<Determinization>,
+    ) {
+        let mut outcomes = self.outcomes(initial_situation);
+        if outcomes.is_determined() {
+            let (destination, _ordering) = outcomes.destinations.pop_first().unwrap();
+            found.push(Determinization { edges: edges.clone(), destination });
+            return;
+        }
+        if outcomes.destinations.is_empty() || edges.len() >= max_edges {
+            return;
+        }
+        // facts may be decided only by several pairs together; then any dependent pair will do
+        let divergences = self.divergences(initial_situation);
+        let deciding_pair = divergences.iter().find_map(|d| d.deciding_pairs.first().cloned());
+        let pairs = self.unordered_dependent_pairs();
+        let blocked_pair = outcomes.blocked.iter().find_map(|blocked| {
+            let blocked_idx = self.index_of[&blocked.event_instance];
+            pairs.iter().find(|pair| pair.contains(&blocked_idx))
+        });
+        let [a, b] = match deciding_pair {
+            Some(pair) => pair,
+            None => match blocked_pair.or(pairs.first()) {
+                Some(&[a, b]) => [self.events[a].clone(), self.events[b].clone()],
+                None => return,
+            },
+        };
+        for edge in [[a.clone(), b.clone()], [b, a]] {
+            let mut ordered = self.clone();
+            ordered.insert_before(&edge);
+            edges.push(edge);
+            ordered.determinize_from(initial_situation, max_edges, edges, found);
+            edges.pop();
+        }
+    }
+    fn outcomes(&self, initial_situation: &Situation) -> Outcomes {
+        let mut linear_extensions = LinearExtensions::new(self);
+        let events_len = linear_extensions.events.len();
+        let mut done = IndexBits::new(events_len);
+        linear_extensions.explore(&mut done, initial_situation, IndexBits::new(events_len));
+        linear_extensions.outcomes
+    }
+}
+impl Compose<Self> for EventGraph {
+    type Error = ComposeError;
+    /// Fails if an index would name different events, or if the composed `before`
+    /// relation would be cyclic.
+    fn compose(&mut self, rhs: &Self) -> Result<(), ComposeError> {
+        let clashes = index_clashes(&self.happen, &rhs.happen);
+        if !clashes.is_empty() {
+            return Err(ComposeError::IndexClashes(clashes));
+        }
+        let mut closed_before = self.closed_before();
+        for ei in rhs.happen.iter() {
+            closed_before.insert_event(ei);
+        }
+        // edges of `self` may join events that only `rhs` has
+        let cyclic = closed_before.take_cycle().is_some()
+            || self.before.iter().chain(rhs.before.iter()).any(|edge| {
+                closed_before.insert_before(edge);
+                closed_before.contains(&[edge[0].clone(), edge[0].clone()])
+            });
+        let mut composed = self.clone();
+        composed.happen.extend(rhs.happen.iter().cloned());
+        composed.before.extend(rhs.before.iter().cloned());
+        if cyclic {
+            let cycle = composed.find_cycle().unwrap_or_default();
+            return Err(ComposeError::Cycle(OrderCycle { cycle }));
+        }
+        *self = composed;
+        Ok(())
+    }
+}
+impl std::fmt::Display for Event {
+    /// Writes e.g. `SetOwner(1)` for event `SetOwner` with argument 1.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}(", self.name)?;
+        for (i, arg) in self.args.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{arg}")?;
+        }
+        write!(f, ")")
+    }
+}
+impl std::fmt::Display for EventInstance {
+    /// Writes e.g. `SetOwner(1)#10` for index 10 of event `SetOwner` with argument 1.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}#{}", self.event, self.index)
+    }
+}
+impl std::fmt::Display for IndexClash {
+    /// Writes e.g. `index 3 names SetOwner(0) and SetOwner(1)`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "index {} names ", self.index)?;
+        for (i, event) in self.events.iter().enumerate() {
+            match i {
+                0 => {}
+                _ if i + 1 == self.events.len() => write!(f, " and ")?,
+                _ => write!(f, ", ")?,
+            }
+            write!(f, "{event}")?;
+        }
+        Ok(())
+    }
+}
+impl std::fmt::Display for ComposeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Cycle(cycle) => write!(f, "composed order is cyclic: {cycle}"),
+            Self::IndexClashes(clashes) => {
+                write!(f, "composed events clash")?;
+                for clash in clashes.iter() {
+                    write!(f, "\n  {clash}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+impl EventGraph {
+    fn check_events(&self) -> Result<(), TaskError> {
+        check_event_instances(self.happen.iter())
+    }
+    /// Maps each event missing from `happen`, though before some of its events, to those
+    /// events. Events are before others by `global_before`, or by this graph's own edges.
+    fn missing_predecessors(
+        &self,
+        global_before: &ClosedOrder,
+    ) -> BTreeMap<EventInstance, BTreeSet<EventInstance>> {
+        let mut missing = BTreeMap::<_, BTreeSet<_>>::default();
+        let edges = global_before
+            .events
+            .iter()
+            .flat_map(|pred| self.happen.iter().map(move |ei| [pred, ei]));
+        for [pred, ei] in edges {
+            if !self.happen.contains(pred) && global_before.contains(&[pred.clone(), ei.clone()]) {
+                missing.entry(pred.clone()).or_default().insert(ei.clone());
+            }
+        }
+        for [a, b] in self.before.iter() {
+            if !self.happen.contains(a) && self.happen.contains(b) {
+                missing.entry(a.clone()).or_default().insert(b.clone());
+            }
+        }
+        missing
+    }
+    /// The events of `happen`, and those named only by `before` edges.
+    fn named_events(&self) -> impl Iterator<Item = &EventInstance> + '_ {
+        self.happen.iter().chain(self.before.iter().flatten())
+    }
+    /// Each `[a, b]` of the same event under different indices, with `a` only in this graph
+    /// and `b` only in `rhs`. These may be one occurrence, indexed independently twice.
+    fn suspected_duplicates(&self, rhs: &Self) -> Vec<[EventInstance; 2]> {
+        let mut duplicates = vec![];
+        for a in self.happen.difference(&rhs.happen) {
+            for b in rhs.happen.difference(&self.happen) {
+                if a.event == b.event {
+                    duplicates.push([a.clone(), b.clone()]);
+                }
+            }
+        }
+        duplicates.sort();
+        duplicates
+    }
+    /// Returns the subgraph of `roots` in `happen`, everything before them, and the
+    /// `before` edges between those events.
+    fn down_closure<'a>(&self, roots: impl IntoIterator<Item = &'a EventInstance>) -> Self {
+        let closed_before = self.closed_before();
+        let mut happen = HashSet::default();
+        for root in roots.into_iter().filter(|root| self.happen.contains(root)) {
+            let preds = self
+                .happen
+                .iter()
+                .filter(|ei| closed_before.contains(&[(*ei).clone(), root.clone()]));
+            happen.extend(preds.cloned());
+            happen.insert(root.clone());
+        }
+        let before = self
+            .before
+            .iter()
+            .filter(|[a, b]| happen.contains(a) && happen.contains(b))
+            .cloned()
+            .collect();
+        Self { happen, before }
+    }
+    /// Returns the graph with the same `happen` events and closed order, with the fewest
+    /// `before` edges between them, i.e. only edges from each event to those it covers.
+    /// Edges involving events not in `happen` are kept as they are.
+    fn transitive_reduction(&self) -> Result<Self, OrderCycle> {
+        if let Some(cycle) = self.find_cycle() {
+            return Err(OrderCycle { cycle });
+        }
+        let closed_before = self.closed_before();
+        let mut before: HashSet<_> = self
+            .before
+            .iter()
+            .filter(|[a, b]| !self.happen.contains(a) || !self.happen.contains(b))
+            .cloned()
+            .collect();
+        for (a, after_a) in closed_before.after.iter().enumerate() {
+            let mut covered = after_a.clone();
+            for via in after_a.iter() {
+                covered.difference_with(&closed_before.after[via]);
+            }
+            before.extend(
+                covered
+                    .iter()
+                    .map(|b| [closed_before.events[a].clone(), closed_before.events[b].clone()]),
+            );
+        }
+        Ok(Self { happen: self.happen.clone(), before })
+    }
+    /// Renders `happen` events as nodes, and `before` edges between them as edges, in Graphviz DOT.
+    /// Nodes and edges are coloured by the `agent_histories` containing them, if any are given.
+    fn to_dot(&self, agent_histories: &[(&Agent, &EventGraph)]) -> String {
+        use std::fmt::Write;
+        let colours_of = |contains: &dyn Fn(&EventGraph) -> bool| {
+            let colours: Vec<_> = agent_histories
+                .iter()
+                .enumerate()
+                .filter(|(_i, (_agent, local))| contains(local))
+                .map(|(i, _)| DOT_COLOURS[i % DOT_COLOURS.len()])
+                .collect();
+            colours.join(":")
+        };
+        let mut events: Vec<_> = self.happen.iter().collect();
+        events.sort();
+        let node_id: HashMap<_, _> = events.iter().enumerate().map(|(i, &ei)| (ei, i)).collect();
+        let mut dot = String::from("digraph EventGraph {\n    node [shape=box];\n");
+        for (i, ei) in events.iter().enumerate() {
+            write!(dot, "    e{i} [label=\"{}\"", dot_escape(&ei.to_string())).unwrap();
+            let colours = colours_of(&|local| local.happen.contains(ei));
+            if !colours.is_empty() {
+                write!(dot, ", style=striped, fillcolor=\"{colours}\"").unwrap();
+            }
+            writeln!(dot, "];").unwrap();
+        }
+        let mut edges: Vec<_> = self
+            .before
+            .iter()
+            .filter_map(|edge| Some((node_id.get(&edge[0])?, node_id.get(&edge[1])?, edge)))
+            .collect();
+        edges.sort();
+        for (a, b, edge) in edges {
+            write!(dot, "    e{a} -> e{b}").unwrap();
+            let colours = colours_of(&|local| local.before.contains(edge));
+            if !colours.is_empty() {
+                write!(dot, " [color=\"{colours}\"]").unwrap();
+            }
+            writeln!(dot, ";").unwrap();
+        }
+        if !agent_histories.is_empty() {
+            writeln!(dot, "    subgraph cluster_agents {{\n        label=\"agents\";").unwrap();
+            for (i, (agent, _local)) in agent_histories.iter().enumerate() {
+                let colour = DOT_COLOURS[i % DOT_COLOURS.len()];
+                let label = dot_escape(agent);
+                writeln!(
+                    dot,
+                    "        a{i} [label=\"{label}\", style=filled, fillcolor={colour}];"
+                )
+                .unwrap();
+            }
+            writeln!(dot, "    }}").unwrap();
+        }
+        dot.push_str("}\n");
+        dot
+    }
+    /// Prints each edge of the transitive reduction between `happen` events on its own line.
+    fn print_hasse(&self) -> Result<(), OrderCycle> {
+        let reduction = self.transitive_reduction()?;
+        let mut covers: Vec<_> = reduction
+            .before
+            .iter()
+            .filter(|[a, b]| self.happen.contains(a) && self.happen.contains(b))
+            .collect();
+        covers.sort();
+        for [a, b] in covers {
+            println!("{a} -> {b}");
+        }
+        Ok(())
+    }
+    /// Prints each fact whose truth differs across destinations, and the unordered
+    /// pairs of events whose relative order decides it.
+    fn print_divergences(&self, initial_situation: &Situation) {
+        for Divergence { fact, deciding_pairs } in
+            self.closed_before().divergences(initial_situation)
+        {
+            println!("{} differs, decided by:", FactHr(fact));
+            if deciding_pairs.is_empty() {
+                println!("  no single pair");
+            }
+            for [a, b] in deciding_pairs.iter() {
+                println!("  {a} vs {b}");
+            }
+        }
+    }
+    /// Returns the facts matching `filter`, if given, that hold in the destinations as `modality` requires.
+    /// No facts are necessary if there are no destinations.
+    fn modal_facts(
+        &self,
+        initial_situation: &Situation,
+        modality: Modality,
+        filter: Option<FactPattern>,
+    ) -> BTreeSet<Fact> {
+        let destinations = self.destinations(initial_situation);
+        let possible: BTreeSet<Fact> = destinations
+            .keys()
+            .flat_map(|situation| situation.truth.iter().filter(|(_fact, &value)| value))
+            .map(|(&fact, _value)| fact)
+            .filter(|&fact| filter.is_none_or(|filter| fact.matches_pattern(filter)))
+            .collect();
+        let mut necessary = possible.clone();
+        necessary.retain(|&fact| destinations.keys().all(|situation| situation.holds(fact)));
+        match modality {
+            Modality::Necessary => necessary,
+            Modality::Possible => possible,
+            Modality::Contested => &possible - &necessary,
+        }
+    }
+    /// Prints, for each destination some extra edges can force, the smallest sets of such edges.
+    fn print_determinizations(&self, initial_situation: &Situation, max_edges: usize) {
+        let determinizations = self.closed_before().determinizations(initial_situation, max_edges);
+        let mut by_destination = BTreeMap::<&Situation, Vec<&[[EventInstance; 2]]>>::default();
+        for Determinization { edges, destination } in determinizations.iter() {
+            by_destination.entry(destination).or_default().push(edges);
+        }
+        if by_destination.is_empty() {
+            println!(
+                "no set of at most {max_edges} edges makes every ordering reach one destination"
+            );
+        }
+        for (destination, mut edge_sets) in by_destination {
+            let fewest = edge_sets.iter().map(|edges| edges.len()).min().unwrap_or(0);
+            edge_sets.retain(|edges| edges.len() == fewest);
+            let facts: Vec<_> = destination
+                .truth
+                .iter()
+                .filter(|(_fact, &value)| value)
+                .map(|(&fact, _value)| FactHr(fact).to_string())
+                .collect();
+            println!("destination: {}", facts.join(", "));
+            for edges in edge_sets {
+                if edges.is_empty() {
+                    println!("  already reached by every ordering, and none blocks");
+                }
+                let edges: Vec<_> = edges.iter().map(|[a, b]| format!("{a} -> {b}")).collect();
+                if !edges.is_empty() {
+                    println!("  with {}", edges.join(", "));
+                }
+            }
+        }
+    }
+    /// Finds some cycle of `before` edges between `happen` events, starting at the first
+    /// event found to be before itself, and following the fewest edges back to it.
+    fn find_cycle(&self) -> Option<Vec<EventInstance>> {
+        let closed_before = self.closed_before();
+        let start = closed_before.take_cycle()?;
+        let mut succs = HashMap::<&EventInstance, Vec<&EventInstance>>::default();
+        for [a, b] in self.before.iter() {
+            if self.happen.contains(a) && self.happen.contains(b) {
+                succs.entry(a).or_default().push(b);
+            }
+        }
+        // breadth-first search from `start`, remembering how each event was first reached
+        let mut reached_from = HashMap::<&EventInstance, &EventInstance>::default();
+        let mut frontier = vec![start];
+        while !reached_from.contains_key(start) {
+            let mut next_frontier = vec![];
+            for &a in frontier.iter() {
+                for &b in succs.get(a).into_iter().flatten() {
+                    if !reached_from.contains_key(b) {
+                        reached_from.insert(b, a);
+                        next_frontier.push(b);
+                    }
+                }
+            }
+            frontier = next_frontier;
+        }
+        let mut cycle = vec![start.clone()];
+        let mut at = reached_from[start];
+        while at != start {
+            cycle.push(at.clone());
+            at = reached_from[at];
+        }
+        cycle.reverse();
+        cycle.rotate_right(1);
+        Some(cycle)
+    }
+    fn closed_before(&self) -> ClosedOrder {
+        ClosedOrder::new(&self.happen, &self.before)
+    }
+    fn destinations(
+        &self,
+        initial_situation: &Situation,
+    ) -> BTreeMap<Situation, Vec<EventInstance>> {
+        self.outcomes(initial_situation).destinations
+    }
+    /// Like `destinations`, but also reports the orderings that got blocked along the way.
+    fn outcomes(&self, initial_situation: &Situation) -> Outcomes {
+        self.closed_before().outcomes(initial_situation)
+    }
+}
+impl Debug for Situation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        struct Filtered<'a>(&'a Situation, bool);
+        impl Debug for Filtered<'_> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                let iter = self
+                    .0
+                    .truth
+                    .iter()
+                    .filter(|&(_fact, &value)| value == self.1)
+                    .map(|(&fact, _value)| FactHr(fact));
+                f.debug_set().entries(iter).finish()
+            }
+        }
+        f.debug_struct("Situation")
+            .field("true", &Filtered(self, true))
+            .field("false", &Filtered(self, false))
+            .finish()
+    }
+}
+impl std::fmt::Display for Blocked {
+    /// Writes e.g. `BecomeFriends(0,1)#3 after SetOwner(0)#0, where exists(who: 0) holds`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.event_instance)?;
+        if !self.ordering.is_empty() {
+            let ordering: Vec<_> = self.ordering.iter().map(ToString::to_string).collect();
+            write!(f, " after {}", ordering.join(", "))?;
+        }
+        let facts: Vec<_> = self
+            .situation
+            .truth
+            .iter()
+            .filter(|(_fact, &value)| value)
+            .map(|(&fact, _value)| FactHr(fact).to_string())
+            .collect();
+        match facts.len() {
+            0 => write!(f, ", where no fact holds"),
+            1 => write!(f, ", where {} holds", facts[0]),
+            _ => write!(f, ", where {} hold", facts.join(", ")),
+        }
+    }
+}
+impl std::fmt::Display for OrderCycle {
+    /// Writes e.g. `Arrive(0)#0 -> Arrive(1)#1 -> Arrive(0)#0`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for ei in self.cycle.iter() {
+            write!(f, "{ei} -> ")?;
+        }
+        match self.cycle.first() {
+            Some(first) => write!(f, "{first}"),
+            None => write!(f, "(empty cycle)"),
+        }
+    }
+}
+impl std::fmt::Display for GlobalConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let cycle = OrderCycle { cycle: self.cycle.clone() };
+        write!(f, "agent histories together order {cycle}")?;
+        for ([a, b], agents) in self.edge_agents.iter() {
+            write!(f, "\n  {a} -> {b}")?;
+            match agents.is_empty() {
+                true => write!(f, " (implied, not a direct edge of any agent)")?,
+                false => write!(f, " by {}", agents.join(", "))?,
+            }
+        }
+        Ok(())
+    }
+}
+impl std::fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SchemaError::Io(e) => write!(f, "{e}"),
+            SchemaError::Parse(e) => write!(f, "{e}"),
+            SchemaError::TooManyKinds { kinds_len } => {
+                write!(f, "{kinds_len} fact kinds are declared, but at most 256 can be")
+            }
+            SchemaError::FactTooWide { kind_name, bits_len } => write!(
+                f,
+                "facts of kind `{kind_name}` need {bits_len} bits, but only {} fit in a fact",
+                FactBits::BITS
+            ),
+            SchemaError::EmptyDomain { kind_name, field_name } => {
+                write!(f, "field `{field_name}` of kind `{kind_name}` has an empty domain")
+            }
+            SchemaError::DuplicateEvent { event_name } => {
+                write!(f, "event `{event_name}` is declared more than once")
+            }
+            SchemaError::UnknownKind { event_name, kind_name } => {
+                write!(f, "event `{event_name}` names unknown fact kind `{kind_name}`")
+            }
+            SchemaError::UnknownField { event_name, kind_name, field_name } => write!(
+                f,
+                "event `{event_name}` names field `{field_name}`, which kind `{kind_name}` lacks"
+            ),
+            SchemaError::UnknownParam { event_name, param } => {
+                write!(f, "event `{event_name}` uses `{param}`, which is not among its params")
+            }
+            SchemaError::UnboundField { event_name, kind_name, field_name } => write!(
+                f,
+                "event `{event_name}` creates `{kind_name}` facts without giving field `{field_name}`"
+            ),
+        }
+    }
+}
+impl std::fmt::Display for PackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PackError::UnknownKind { kind_idx } => write!(f, "no fact kind has index {kind_idx}"),
+            PackError::WrongFieldCount { kind_name, expected, found } => {
+                write!(f, "kind `{kind_name}` has {expected} fields, but {found} were given")
+            }
+            PackError::Overflow { kind_name, field_name, value, domain } => write!(
+                f,
+                "value {value} of field `{field_name}` of kind `{kind_name}` is not in 0..{domain}"
+            ),
+        }
+    }
+}
+impl std::fmt::Display for TaskError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TaskError::UnknownEvent { event } => write!(
+                f,
+                "schema defines no event `{}` with {} parameters, as {event} needs",
+                event.name,
+                event.args.len()
+            ),
+            TaskError::UnknownAgent { agent } => write!(f, "no agent is named `{agent}`"),
+            TaskError::AgentExists { agent } => write!(f, "agent `{agent}` already exists"),
+            TaskError::Io(e) => write!(f, "{e}"),
+            TaskError::Ron(e) => write!(f, "{e}"),
+            TaskError::Cycle(cycle) => write!(f, "order is cyclic: {cycle}"),
+            TaskError::Compose(e) => write!(f, "{e}"),
+            TaskError::GlobalConflict(conflict) => write!(f, "{conflict}"),
+            TaskError::UnknownKind { kind } => write!(f, "schema defines no fact kind `{kind}`"),
+            TaskError::UnknownField { kind, field } => {
+                write!(f, "fact kind `{kind}` has no field `{field}`")
+            }
+            TaskError::PatternSyntax { pattern } => write!(f, "cannot parse pattern `{pattern}`"),
+            TaskError::Pack(e) => write!(f, "{e}"),
+            TaskError::ArgOutOfDomain { event } => {
+                write!(f, "some argument of {event} is outside the domain of its field")
+            }
+            TaskError::UnknownPartial { name } => write!(f, "no partial graph is named `{name}`"),
+            TaskError::PartialExists { name } => {
+                write!(f, "partial graph `{name}` already exists")
+            }
+            TaskError::Incomplete { unmet } => {
+                write!(f, "destinations would ignore unmet dependencies")?;
+                for ei in unmet.iter() {
+                    write!(f, "\n  {ei}")?;
+                }
+                Ok(())
+            }
+            TaskError::IndicesExhausted => write!(f, "every index is in use or was handed out"),
+        }
+    }
+}
+/// A kind or field name, read as an identifier rather than a string.
+struct Identifier(String);
+impl<'de> Deserialize<'de> for Identifier {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        struct IdentifierVisitor;
+        impl<'de> de::Visitor<'de> for IdentifierVisitor {
+            type Value = Identifier;
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "a kind or field name")
+            }
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Identifier, E> {
+                Ok(Identifier(v.to_owned()))
+            }
+        }
+        d.deserialize_identifier(IdentifierVisitor)
+    }
+}
+/// Deserializes nothing, but records the variant names that a derived `Deserialize`
+/// passes for an enum.
+struct VariantNamesCapture<'a>(&'a mut &'static [&'static str]);
+impl<'de> Deserializer<'de> for VariantNamesCapture<'_> {
+    type Error = de::value::Error;
+    fn deserialize_any<V: de::Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("not an enum"))
+    }
+    fn deserialize_enum<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        variants: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = variants;
+        Err(de::Error::custom("variant names captured"))
+    }
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map struct identifier
+        ignored_any
+    }
+}
+/// Reads `kind(field: value, ..)` as the kind's index, and the value given for each of its fields.
+struct FactFieldsVisitor;
+impl<'de> de::Visitor<'de> for FactFieldsVisitor {
+    type Value = (u8, Vec<Option<u32>>);
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "a fact, like `kind(field: value, ..)`")
+    }
+    fn visit_enum<A: de::EnumAccess<'de>>(self, data: A) -> Result<Self::Value, A::Error> {
+        let schema = schema();
+        let (Identifier(kind_name), variant) = data.variant()?;
+        let kind_idx = schema.kind_idx(&kind_name).ok_or_else(|| {
+            let kind_names: Vec<_> = schema.kind_metas.iter().map(|fkm| &fkm.kind_name).collect();
+            de::Error::custom(format!(
+                "unknown fact kind `{kind_name}`, expected one of {kind_names:?}"
+            ))
+        })?;
+        let fkm = &schema.kind_metas[kind_idx as usize];
+        if fkm.field_metas.is_empty() {
+            de::VariantAccess::unit_variant(variant)?;
+            return Ok((kind_idx, vec![]));
+        }
+        let field_values =
+            de::VariantAccess::struct_variant(variant, &[], FieldValuesVisitor(fkm))?;
+        Ok((kind_idx, field_values))
+    }
+}
+/// Reads `(field: value, ..)` as the value given for each field of the kind.
+struct FieldValuesVisitor(&'static FactKindMeta);
+impl<'de> de::Visitor<'de> for FieldValuesVisitor {
+    type Value = Vec<Option<u32>>;
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "the fields of kind `{}`", self.0.kind_name)
+    }
+    fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let fkm = self.0;
+        let mut field_values = vec![None; fkm.field_metas.len()];
+        while let Some(Identifier(field_name)) = map.next_key()? {
+            let field_idx =
+                fkm.field_metas.iter().position(|fm| fm.field_name == field_name).ok_or_else(
+                    || {
+                        let kind_name = &fkm.kind_name;
+                        de::Error::custom(format!("kind `{kind_name}` has no field `{field_name}`"))
+                    },
+                )?;
+            if field_values[field_idx].replace(map.next_value()?).is_some() {
+                return Err(de::Error::custom(format!("duplicate field `{field_name}`")));
+            }
+        }
+        Ok(field_values)
+    }
+}
+impl Serialize for Fact {
+    /// Writes e.g. `friend(a: 1, b: 2)`, naming the kind and fields as the schema does.
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        FactPattern { fact: *self, mask: !0 }.serialize(s)
+    }
+}
+impl<'de> Deserialize<'de> for Fact {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let (kind_idx, field_values) = d.deserialize_enum("Fact", &[], FactFieldsVisitor)?;
+        let fkm = &schema().kind_metas[kind_idx as usize];
+        let field_values = field_values
+            .iter()
+            .zip(fkm.field_metas.iter())
+            .map(|(value, field_meta)| {
+                value.ok_or_else(|| {
+                    let (kind_name, field_name) = (&fkm.kind_name, &field_meta.field_name);
+                    de::Error::custom(format!("missing field `{field_name}` of kind `{kind_name}`"))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Fact::try_pack(kind_idx, &field_values).map_err(de::Error::custom)
+    }
+}
+impl Serialize for FactPattern {
+    /// Writes e.g. `friend(a: 1)`, omitting fields that match any value.
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let schema = schema();
+        let kind_bits_len = schema.kind_bits_len();
+        let kind_mask = bit_mask(0..kind_bits_len);
+        let kind_idx = self.fact.read(0..kind_bits_len);
+        let fkm = match schema.kind_metas.get(kind_idx as usize) {
+            Some(fkm) if self.mask & kind_mask == kind_mask => fkm,
+            _ => return Err(ser::Error::custom("fact pattern does not fix a known kind")),
+        };
+        let fixed_fields: Vec<_> = fkm
+            .field_ranges(kind_bits_len)
+            .filter(|(_field_meta, bit_range)| {
+                let field_mask = bit_mask(range_copy(bit_range));
+                self.mask & field_mask == field_mask
+            })
+            .collect();
+        let kind_name = fkm.kind_name.as_str();
+        if fkm.field_metas.is_empty() {
+            return s.serialize_unit_variant("Fact", kind_idx, kind_name);
+        }
+        let mut sv = s.serialize_struct_variant("Fact", kind_idx, kind_name, fixed_fields.len())?;
+        for (field_meta, bit_range) in fixed_fields {
+            ser::SerializeStructVariant::serialize_field(
+                &mut sv,
+                field_meta.field_name.as_str(),
+                &self.fact.read(bit_range),
+            )?;
+        }
+        ser::SerializeStructVariant::end(sv)
+    }
+}
+impl<'de> Deserialize<'de> for FactPattern {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let (kind_idx, field_values) = d.deserialize_enum("Fact", &[], FactFieldsVisitor)?;
+        FactPattern::try_of_fields(kind_idx, &field_values).map_err(de::Error::custom)
+    }
+}
+impl From<SituationRepr> for Situation {
+    fn from(repr: SituationRepr) -> Self {
+        let true_facts = repr.true_facts.into_iter().map(|fact| (fact, true));
+        let false_facts = repr.false_facts.into_iter().map(|fact| (fact, false));
+        Self { truth: false_facts.chain(true_facts).collect() }
+    }
+}
+impl From<Situation> for SituationRepr {
+    fn from(situation: Situation) -> Self {
+        let (true_facts, false_facts) =
+            situation.truth.into_iter().partition(|&(_fact, value)| value);
+        let facts =
+            |truth: Vec<(Fact, bool)>| truth.into_iter().map(|(fact, _value)| fact).collect();
+        Self { true_facts: facts(true_facts), false_facts: facts(false_facts) }
+    }
+}
+impl Compose<Self> for PartialEventGraph {
+    type Error = ComposeError;
+    fn compose(&mut self, rhs: &Self) -> Result<(), ComposeError> {
+        self.event_graph.compose(&rhs.event_graph)?;
+        self.depend.extend(rhs.depend.iter().cloned());
+        Ok(())
+    }
+}
+impl Lattice for EventGraph {
+    fn meet(&self, rhs: &Self) -> Self {
+        let happen: HashSet<_> = self.happen.intersection(&rhs.happen).cloned().collect();
+        let rhs_before = rhs.closed_before();
+        let before = self
+            .closed_before()
+            .edges()
+            .filter(|edge| edge.iter().all(|ei| happen.contains(ei)) && rhs_before.contains(edge))
+            .collect();
+        Self { happen, before }
+    }
+    /// Keeps this graph's orderings that `rhs` doesn't imply, with its edges to events
+    /// outside `happen`, so that composing the difference into `rhs` closes to the same
+    /// order as composing this graph into it.
+    fn difference(&self, rhs: &Self) -> Self {
+        let happen = self.happen.difference(&rhs.happen).cloned().collect();
+        let rhs_before = rhs.closed_before();
+        let mut before: HashSet<_> =
+            self.closed_before().edges().filter(|edge| !rhs_before.contains(edge)).collect();
+        before.extend(
+            self.before
+                .iter()
+                .filter(|[a, b]| !self.happen.contains(a) || !self.happen.contains(b))
+                .cloned(),
+        );
+        Self { happen, before }
+    }
+    fn is_sub_history_of(&self, rhs: &Self) -> bool {
+        let rhs_before = rhs.closed_before();
+        self.happen.is_subset(&rhs.happen)
+            && self.closed_before().edges().all(|edge| rhs_before.contains(&edge))
+    }
+    fn is_prefix_of(&self, rhs: &Self) -> bool {
+        let self_before = self.closed_before();
+        self.is_sub_history_of(rhs)
+            && rhs
+                .closed_before()
+                .edges()
+                .all(|[a, b]| !self.happen.contains(&b) || self_before.contains(&[a, b]))
+    }
+}
+impl Lattice for PartialEventGraph {
+    /// Depends on what both depend on.
+    fn meet(&self, rhs: &Self) -> Self {
+        let depend = self.depend.intersection(&rhs.depend).cloned().collect();
+        Self { depend, event_graph: self.event_graph.meet(&rhs.event_graph) }
+    }
+    /// Depends on what only this graph depends on.
+    fn difference(&self, rhs: &Self) -> Self {
+        let depend = self.depend.difference(&rhs.depend).cloned().collect();
+        Self { depend, event_graph: self.event_graph.difference(&rhs.event_graph) }
+    }
+    /// Additionally, each dependency of this graph is a dependency or event of `rhs`.
+    fn is_sub_history_of(&self, rhs: &Self) -> bool {
+        self.event_graph.is_sub_history_of(&rhs.event_graph)
+            && self
+                .depend
+                .iter()
+                .all(|ei| rhs.depend.contains(ei) || rhs.event_graph.happen.contains(ei))
+    }
+    fn is_prefix_of(&self, rhs: &Self) -> bool {
+        self.is_sub_history_of(rhs) && self.event_graph.is_prefix_of(&rhs.event_graph)
+    }
+}
+/// Prints the events, and the edges of the transitive reduction.
+fn print_summary(graph: &EventGraph) -> Result<(), OrderCycle> {
+    let mut events: Vec<_> = graph.happen.iter().map(ToString::to_string).collect();
+    events.sort();
+    if events.is_empty() {
+        events.push("none".to_owned());
+    }
+    println!("  events: {}", events.join(", "));
+    let mut covers: Vec<_> = graph.transitive_reduction()?.before.into_iter().collect();
+    covers.sort();
+    for [a, b] in covers {
+        println!("  {a} -> {b}");
+    }
+    Ok(())
+}
+/// Like `print_summary`, followed by the dependencies, if any.
+fn print_partial_summary(partial: &PartialEventGraph) -> Result<(), OrderCycle> {
+    print_summary(&partial.event_graph)?;
+    let mut depend: Vec<_> = partial.depend.iter().map(ToString::to_string).collect();
+    depend.sort();
+    if !depend.is_empty() {
+        println!("  depends on: {}", depend.join(", "));
+    }
+    Ok(())
+}
+/// Prints what the two histories share, what each has that the other lacks, and whether
+/// either is a sub-history or prefix of the other. Each part is printed by `summary`.
+fn print_comparison<L: Lattice>(
+    [lhs_name, rhs_name]: [&str; 2],
+    [lhs, rhs]: [&L; 2],
+    summary: fn(&L) -> Result<(), OrderCycle>,
+) -> Result<(), OrderCycle> {
+    println!("common to {lhs_name} and {rhs_name}:");
+    summary(&lhs.meet(rhs))?;
+    println!("only in {lhs_name}:");
+    summary(&lhs.difference(rhs))?;
+    println!("only in {rhs_name}:");
+    summary(&rhs.difference(lhs))?;
+    for (a_name, a, b_name, b) in [(lhs_name, lhs, rhs_name, rhs), (rhs_name, rhs, lhs_name, lhs)] {
+        if a.is_prefix_of(b) {
+            println!("{a_name} is a prefix of {b_name}");
+        } else if a.is_sub_history_of(b) {
+            println!("{a_name} is a sub-history of {b_name}");
+        }
+    }
+    Ok(())
+}
+impl std::str::FromStr for FactPattern {
+    type Err = TaskError;
+    /// Parses e.g. `friend(a: 1, b: _)`, `owner(_)` or `exists`, where `_` and omitted
+    /// fields match any value. Fields are named, or given in the schema's order.
+    /// Printed facts parse as patterns matching only themselves. `a=1` is read as `a: 1`.
+    fn from_str(s: &str) -> Result<Self, TaskError> {
+        let syntax_error = || TaskError::PatternSyntax { pattern: s.to_owned() };
+        let (kind, args) = match s.trim().split_once('(') {
+            Some((kind, rest)) => {
+                (kind.trim(), rest.trim_end().strip_suffix(')').ok_or_else(syntax_error)?)
+            }
+            None => (s.trim(), ""),
+        };
+        if kind.is_empty() || !kind.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(syntax_error());
+        }
+        let schema = schema();
+        let kind_idx = schema
+            .kind_idx(kind)
+            .ok_or_else(|| TaskError::UnknownKind { kind: kind.to_owned() })?;
+        let fkm = &schema.kind_metas[kind_idx as usize];
+        let args: Vec<&str> =
+            if args.trim().is_empty() { vec![] } else { args.split(',').map(str::trim).collect() };
+        let named = args.iter().any(|arg| arg.contains([':', '=']));
+        let parse_value = |value: &str| match value {
+            "_" => Ok(None),
+            value => value.parse().map(Some).map_err(|_| syntax_error()),
+        };
+        let field_values = if named {
+            let mut field_values = vec![None; fkm.field_metas.len()];
+            for arg in args.iter() {
+                let (field, value) = arg.split_once([':', '=']).ok_or_else(syntax_error)?;
+                let field = field.trim();
+                let field_idx =
+                    fkm.field_metas.iter().position(|fm| fm.field_name == field).ok_or_else(
+                        || {
+                            let (kind, field) = (kind.to_owned(), field.to_owned());
+                            TaskError::UnknownField { kind, field }
+                        },
+                    )?;
+                if field_values[field_idx].is_some() {
+                    return Err(syntax_error());
+                }
+                field_values[field_idx] = parse_value(value.trim())?;
+            }
+            field_values
+        } else if args.is_empty() {
+            vec![None; fkm.field_metas.len()]
+        } else {
+            args.iter().map(|arg| parse_value(arg)).collect::<Result<_, _>>()?
+        };
+        FactPattern::try_of_fields(kind_idx, &field_values).map_err(TaskError::Pack)
+    }
+}
+impl PartialEventGraph {
+    fn is_complete(&self) -> bool {
+        self.depend.is_subset(&self.event_graph.happen)
+    }
+    fn unmet(&self) -> Vec<EventInstance> {
+        let mut unmet: Vec<_> = self.depend.difference(&self.event_graph.happen).cloned().collect();
+        unmet.sort();
+        unmet
+    }
+    fn check_events(&self) -> Result<(), TaskError> {
+        self.event_graph.check_events()?;
+        check_event_instances(self.depend.iter())
+    }
+    /// The events its graph names, and its dependencies.
+    fn named_events(&self) -> impl Iterator<Item = &EventInstance> + '_ {
+        self.event_graph.named_events().chain(self.depend.iter())
+    }
+    /// Composes in the minimal causally-closed subgraph of `source` containing each unmet
+    /// dependency it has. Dependencies not in `source` stay unmet.
+    fn resolve_from(&mut self, source: &EventGraph) -> Result<(), ComposeError> {
+        let unmet = self.unmet();
+        self.event_graph.compose(&source.down_closure(unmet.iter()))
+    }
+    /// Refuses incomplete graphs.
+    fn outcomes(&self, initial_situation: &Situation) -> Result<Outcomes, TaskError> {
+        if !self.is_complete() {
+            return Err(TaskError::Incomplete { unmet: self.unmet() });
+        }
+        Ok(self.event_graph.outcomes(initial_situation))
+    }
+}
+/// The indices of `incoming` that would name more than one event, among `known` and `incoming`.
+fn index_clashes<'a, 'b>(
+    known: impl IntoIterator<Item = &'a EventInstance>,
+    incoming: impl IntoIterator<Item = &'b EventInstance>,
+) -> Vec<IndexClash> {
+    let mut events_of: BTreeMap<u32, BTreeSet<&Event>> = BTreeMap::new();
+    for ei in known {
+        events_of.entry(ei.index).or_default().insert(&ei.event);
+    }
+    let mut incoming_indices = BTreeSet::new();
+    for ei in incoming {
+        events_of.entry(ei.index).or_default().insert(&ei.event);
+        incoming_indices.insert(ei.index);
+    }
+    events_of
+        .into_iter()
+        .filter(|(index, events)| events.len() > 1 && incoming_indices.contains(index))
+        .map(|(index, events)| IndexClash { index, events: events.into_iter().cloned().collect() })
+        .collect()
+}
+fn check_event_instances<'a>(
+    eis: impl IntoIterator<Item = &'a EventInstance>,
+) -> Result<(), TaskError> {
+    for ei in eis {
+        if ei.event.def().is_none() {
+            return Err(TaskError::UnknownEvent { event: ei.event.clone() });
+        }
+        if ei.event.footprint().is_none() {
+            return Err(TaskError::ArgOutOfDomain { event: ei.event.clone() });
+        }
+    }
+    Ok(())
+}
+impl Fact {
+    pub const fn matches_pattern(self, fact_pattern: FactPattern) -> bool {
+        self.bits & fact_pattern.mask == fact_pattern.fact.bits
+    }
+    pub fn write(&mut self, fact_pattern: FactPattern) {
+        self.bits &= !fact_pattern.mask;
+        self.bits |= fact_pattern.fact.bits;
+    }
+    pub fn with(mut self, fact_pattern: FactPattern) -> Self {
+        self.write(fact_pattern);
+        self
+    }
+    pub const fn read(self, bit_range: Range<u8>) -> u32 {
+        match (self.bits & bit_mask(range_copy(&bit_range))).checked_shr(bit_range.start as u32) {
+            Some(bits) => bits as u32,
+            None => 0,
+        }
+    }
+}
+impl Task {
+    /// Returns true for tasks that may change the `ReplState`, and thus trigger autosaves.
+    fn is_mutating(&self) -> bool {
+        matches!(
+            self,
+            Task::AgentHistoryAdd { .. }
+                | Task::AgentHistoryReduce { .. }
+                | Task::AgentCreate { .. }
+                | Task::AgentRename { .. }
+                | Task::AgentRemove { .. }
+                | Task::Load { .. }
+                | Task::InitialSituationSet { .. }
+                | Task::InitialSituationExtend { .. }
+                | Task::PartialCreate { .. }
+                | Task::PartialCompose { .. }
+                | Task::PartialResolve { .. }
+                | Task::AgentPredecessorsImport { .. }
+                | Task::AgentEventRecord { .. }
+        )
+    }
+    /// One task of each variant, with placeholder contents.
+    fn examples() -> Vec<Task> {
+        let agent = || "agent".to_owned();
+        let path = || "path".to_owned();
+        let name = || "name".to_owned();
+        // the first kind of the schema, with all fields zero
+        let fields_len = schema().kind_metas.first().map(|fkm| fkm.field_metas.len());
+        let facts = || fields_len.map(|len| Fact::try_pack(0, &vec![0; len]).unwrap());
+        let filter = fields_len.map(|len| FactPattern::try_of_fields(0, &vec![None; len]).unwrap());
+        vec![
+            Task::Help,
+            Task::AgentCreate { agent: agent() },
+            Task::AgentRename { agent: agent(), new_name: "new_name".to_owned() },
+            Task::AgentRemove { agent: agent() },
+            Task::AgentsPrint,
+            Task::AgentHistoryAdd { agent: agent(), graph: EventGraph::default() },
+            Task::AgentHistoryPrint { agent: agent() },
+            Task::AgentHistoryReduce { agent: agent() },
+            Task::AgentHassePrint { agent: agent() },
+            Task::AgentDestinationsPrint { agent: agent() },
+            Task::AgentHistoryDotWrite { agent: agent(), path: path() },
+            Task::AgentDestinationsDotWrite { agent: agent(), path: path() },
+            Task::GlobalHistoryPrint,
+            Task::GlobalHassePrint,
+            Task::GlobalDestinationsPrint,
+            Task::AgentDivergencePrint { agent: agent() },
+            Task::GlobalDivergencePrint,
+            Task::AgentDeterminizePrint { agent: agent(), max_edges: 2 },
+            Task::GlobalDeterminizePrint { max_edges: 2 },
+            Task::AgentModalFactsPrint { agent: agent(), modality: Modality::Necessary, filter },
+            Task::GlobalModalFactsPrint { modality: Modality::Possible, filter: None },
+            Task::Query { pattern: "kind(field: _)".to_owned(), target: QueryTarget::Initial },
+            Task::Query {
+                pattern: "kind(_)".to_owned(),
+                target: QueryTarget::AgentDestinations { agent: agent() },
+            },
+            Task::Query { pattern: "kind".to_owned(), target: QueryTarget::GlobalDestinations },
+            Task::PartialCreate { name: name(), partial: PartialEventGraph::default() },
+            Task::PartialCompose { name: name(), partial: PartialEventGraph::default() },
+            Task::PartialPrint { name: name() },
+            Task::PartialUnmetPrint { name: name() },
+            Task::PartialResolve { name: name() },
+            Task::PartialDestinationsPrint { name: name() },
+            Task::CausalClosureCheck,
+            Task::AgentPredecessorsImport { agent: agent() },
+            Task::AgentsCompare { agent: agent(), other: "other".to_owned() },
+            Task::PartialsCompare { name: name(), other: "other".to_owned() },
+            Task::AgentEventRecord {
+                agent: agent(),
+                event: Event { name: "EventName".to_owned(), args: vec![] },
+            },
+            Task::GlobalHistoryDotWrite { path: path() },
+            Task::GlobalDestinationsDotWrite { path: path() },
+            Task::SchemaPrint,
+            Task::InitialSituationSet { facts: facts().into_iter().collect() },
+            Task::InitialSituationExtend { facts: facts().into_iter().collect() },
+            Task::InitialSituationPrint,
+            Task::Save { path: path() },
+            Task::Load { path: path() },
+            Task::Autosave { path: Some(path()) },
+        ]
+    }
+    /// The RON text of each of the `examples`.
+    fn shapes() -> Vec<String> {
+        Self::examples().iter().map(|task| ron::ser::to_string(task).unwrap()).collect()
+    }
+    /// The name of each variant, in order of declaration.
+    fn variant_names() -> &'static [&'static str] {
+        let mut variant_names: &'static [&'static str] = &[];
+        let _ = Task::deserialize(VariantNamesCapture(&mut variant_names));
+        variant_names
+    }
+}
+impl std::fmt::Display for TaskParseError {
+    /// Renders the position of the error, with a caret under it in the offending line.
+    /// Errors without a position, e.g. missing fields, are placed at the task's name.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name_line = self.text.lines().position(|line| !line.trim().is_empty()).unwrap_or(0);
+        let name_text = self.text.lines().nth(name_line).unwrap_or("");
+        let name_col = name_text.len() - name_text.trim_start().len() + 1;
+        let name = name_text.trim_start();
+        let name =
+            &name[..name.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(name.len())];
+        let variant_names = Task::variant_names();
+        let known = variant_names.contains(&name);
+        let (line, col) = match self.error.position {
+            _ if !known => (name_line + 1, name_col),
+            ron::error::Position { line: 0, .. } => (name_line + 1, name_col),
+            ron::error::Position { line, col } => (line, col),
+        };
+        let line_text = self.text.lines().nth(line - 1).unwrap_or("");
+        write!(f, "line {}, column {}: ", self.first_line + line - 1, col)?;
+        if known {
+            writeln!(f, "{}", self.error.code)?;
+        } else if name.is_empty() {
+            writeln!(f, "expected a task name")?;
+        } else {
+            writeln!(f, "unknown task `{}`", name)?;
+        }
+        writeln!(f, "  {}", line_text)?;
+        write!(f, "  {:>width$}", '^', width = col)?;
+        if !known {
+            write!(f, "\nvalid tasks: {}", variant_names.join(", "))?;
+        }
+        Ok(())
+    }
+}
+/// Returns true if `s` holds nothing but whitespace and RON comments.
+fn is_blank_ron(s: &str) -> bool {
+    let mut rest = s.trim_start();
+    while !rest.is_empty() {
+        rest = if let Some(comment) = rest.strip_prefix("//") {
+            comment.split_once('\n').map_or("", |(_comment, rest)| rest)
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            match comment.split_once("*/") {
+                Some((_comment, rest)) => rest,
+                None => return false,
+            }
+        } else {
+            return false;
+        };
+        rest = rest.trim_start();
+    }
+    true
+}
+impl<R: std::io::BufRead> TaskReader<R> {
+    /// Reads lines until they form a task, or fail to parse as one.
+    /// Returns None once the input ends.
+    fn next_task(&mut self) -> Option<Result<Task, TaskReadError>> {
+        let mut text = String::new();
+        let mut first_line = self.lines_read + 1;
+        loop {
+            if self.interactive {
+                print!("{}", if text.is_empty() { "$ " } else { "> " });
+                std::io::Write::flush(&mut std::io::stdout()).unwrap();
+            }
+            match self.reader.read_line(&mut text) {
+                Err(e) => return Some(Err(TaskReadError::Io(e))),
+                Ok(0) => {
+                    self.first_line = first_line;
+                    return match ron::de::from_str::<Task>(&text) {
+                        _ if is_blank_ron(&text) => None,
+                        Ok(task) => Some(Ok(task)),
+                        Err(error) => Some(Err(TaskReadError::Parse(TaskParseError {
+                            error,
+                            text,
+                            first_line,
+                        }))),
+                    };
+                }
+                Ok(_) => {}
+            }
+            self.lines_read += 1;
+            if is_blank_ron(&text) {
+                text.clear();
+                first_line = self.lines_read + 1;
+                continue;
+            }
+            match ron::de::from_str::<Task>(&text) {
+                Ok(task) => {
+                    self.first_line = first_line;
+                    return Some(Ok(task));
+                }
+                // errors at the very end of the text mean the task continues on the next line
+                Err(error) if error.position.line > text.lines().count() => {}
+                Err(error) => match error.code {
+                    ron::error::ErrorCode::Eof | ron::error::ErrorCode::UnclosedBlockComment => {}
+                    _ => {
+                        return Some(Err(TaskReadError::Parse(TaskParseError {
+                            error,
+                            text,
+                            first_line,
+                        })))
+                    }
+                },
+            }
+        }
+    }
+}
+impl ReplState {
+    fn handle_task(&mut self, task: Task) -> Result<(), TaskError> {
+        let mutating = task.is_mutating();
+        match task {
+            Task::AgentHistoryAdd { agent, graph } => {
+                graph.check_events()?;
+                self.agent_history(&agent)?;
+                self.check_index_clashes(graph.named_events())?;
+                let known = EventGraph {
+                    happen: self.known_events().into_iter().cloned().collect(),
+                    before: HashSet::default(),
+                };
+                self.agent_history_mut(&agent)?.compose(&graph).map_err(TaskError::Compose)?;
+                // events known only to other agents, that this agent may have re-indexed
+                let others = known.difference(self.agent_history(&agent)?);
+                for [a, b] in others.suspected_duplicates(&graph) {
+                    if !known.happen.contains(&b) {
+                        println!("warning: {b} may be the same occurrence as {a}");
+                    }
+                }
+            }
+            Task::AgentHistoryPrint { agent } => println!("{:#?}", self.agent_history(&agent)?),
+            Task::AgentDestinationsPrint { agent } => {
+                self.agent_history(&agent)?.outcomes(&self.initial_situation).print()
+            }
+            Task::GlobalHistoryPrint => println!("{:#?}", &self.global_history()?),
+            Task::SchemaPrint => println!("{:#?}", schema()),
+            Task::AgentHistoryReduce { agent } => {
+                let local = self.agent_history_mut(&agent)?;
+                *local = local.transitive_reduction().map_err(TaskError::Cycle)?;
+            }
+            Task::AgentHassePrint { agent } => {
+                self.agent_history(&agent)?.print_hasse().map_err(TaskError::Cycle)?
+            }
+            Task::AgentDivergencePrint { agent } => {
+                self.agent_history(&agent)?.print_divergences(&self.initial_situation)
+            }
+            Task::GlobalDivergencePrint => {
+                self.global_history()?.print_divergences(&self.initial_situation)
+            }
+            Task::AgentDeterminizePrint { agent, max_edges } => self
+                .agent_history(&agent)?
+                .print_determinizations(&self.initial_situation, max_edges),
+            Task::GlobalDeterminizePrint { max_edges } => {
+                self.global_history()?.print_determinizations(&self.initial_situation, max_edges)
+            }
+            Task::AgentModalFactsPrint { agent, modality, filter } => {
+                let local = self.agent_history(&agent)?;
+                for fact in local.modal_facts(&self.initial_situation, modality, filter) {
+                    println!("{}", FactHr(fact));
+                }
+            }
+            Task::GlobalModalFactsPrint { modality, filter } => {
+                let global = self.global_history()?;
+                for fact in global.modal_facts(&self.initial_situation, modality, filter) {
+                    println!("{}", FactHr(fact));
+                }
+            }
+            Task::Query { pattern, target } => {
+                let fact_pattern = pattern.parse::<FactPattern>()?;
+                let print_matches = |situation: &Situation| {
+                    for (fact, _value) in
+                        situation.query(fact_pattern).filter(|&(_fact, value)| value)
+                    {
+                        println!("{}", FactHr(fact));
+                    }
+                };
+                let print_destinations = |destinations: BTreeMap<Situation, _>| {
+                    for (i, situation) in destinations.keys().enumerate() {
+                        println!("destination {i}:");
+                        print_matches(situation);
+                    }
+                };
+                match target {
+                    QueryTarget::Initial => print_matches(&self.initial_situation),
+                    QueryTarget::AgentDestinations { agent } => print_destinations(
+                        self.agent_history(&agent)?.destinations(&self.initial_situation),
+                    ),
+                    QueryTarget::GlobalDestinations => print_destinations(
+                        self.global_history()?.destinations(&self.initial_situation),
+                    ),
+                }
+            }
+            Task::GlobalHassePrint => {
+                self.global_history()?.print_hasse().map_err(TaskError::Cycle)?
+            }
+            Task::AgentHistoryDotWrite { agent, path } => {
+                let dot = self.agent_history(&agent)?.to_dot(&[]);
+                std::fs::write(path, dot).map_err(TaskError::Io)?
+            }
+            Task::GlobalHistoryDotWrite { path } => {
+                let agent_histories: Vec<_> = self.agent_histories.iter().collect();
+                let dot = self.global_history()?.to_dot(&agent_histories);
+                std::fs::write(path, dot).map_err(TaskError::Io)?
+            }
+            Task::AgentDestinationsDotWrite { agent, path } => {
+                let destinations =
+                    self.agent_history(&agent)?.destinations(&self.initial_situation);
+                std::fs::write(path, destinations_to_dot(&destinations)).map_err(TaskError::Io)?
+            }
+            Task::GlobalDestinationsDotWrite { path } => {
+                let destinations = self.global_history()?.destinations(&self.initial_situation);
+                std::fs::write(path, destinations_to_dot(&destinations)).map_err(TaskError::Io)?
+            }
+            Task::GlobalDestinationsPrint => {
+                self.global_history()?.outcomes(&self.initial_situation).print()
+            }
+            Task::AgentCreate { agent } => {
+                if self.agent_histories.contains_key(&agent) {
+                    return Err(TaskError::AgentExists { agent });
+                }
+                self.agent_histories.insert(agent, EventGraph::default());
+            }
+            Task::AgentRename { agent, new_name } => {
+                if self.agent_histories.contains_key(&new_name) {
+                    return Err(TaskError::AgentExists { agent: new_name });
+                }
+                let local =
+                    self.agent_histories.remove(&agent).ok_or(TaskError::UnknownAgent { agent })?;
+                self.agent_histories.insert(new_name, local);
+            }
+            Task::AgentRemove { agent } => {
+                if self.agent_histories.remove(&agent).is_none() {
+                    return Err(TaskError::UnknownAgent { agent });
+                }
+            }
+            Task::AgentsPrint => println!("{:#?}", self.agent_histories.keys().collect::<Vec<_>>()),
+            Task::InitialSituationSet { facts } => {
+                let mut situation = Situation::default();
+                for fact in facts {
+                    situation.insert(fact, true);
+                }
+                self.initial_situation = situation;
+            }
+            Task::InitialSituationExtend { facts } => {
+                for fact in facts {
+                    self.initial_situation.insert(fact, true);
+                }
+            }
+            Task::InitialSituationPrint => {
+                for (&fact, _value) in
+                    self.initial_situation.truth.iter().filter(|(_fact, &value)| value)
+                {
+                    println!("{}", FactHr(fact));
+                }
+            }
+            Task::Help => {
+                println!("tasks:");
+                for shape in Task::shapes() {
+                    println!("  {}", shape);
+                }
+                println!(
+                    "events, within `EventInstance(event: Event(name: _, args: [..]), index: _)`:"
+                );
+                for event_def in schema().event_defs.iter() {
+                    println!("  {}({})", event_def.event_name, event_def.params.join(", "));
+                }
+                println!("agents:");
+                for agent in self.agent_histories.keys() {
+                    println!("  {}", agent);
+                }
+            }
+            Task::Save { path } => self.save(&path)?,
+            Task::Load { path } => {
+                let s = std::fs::read_to_string(path).map_err(TaskError::Io)?;
+                let loaded: Self = ron::de::from_str(&s).map_err(TaskError::Ron)?;
+                let loaded = Self::rebuild(loaded)?;
+                self.initial_situation = loaded.initial_situation;
+                self.agent_histories = loaded.agent_histories;
+                self.partial_graphs = loaded.partial_graphs;
+                self.next_index = loaded.next_index;
+            }
+            Task::Autosave { path } => self.autosave = path,
+            Task::PartialCreate { name, partial } => {
+                partial.check_events()?;
+                if self.partial_graphs.contains_key(&name) {
+                    return Err(TaskError::PartialExists { name });
+                }
+                self.check_index_clashes(partial.named_events())?;
+                let mut created = PartialEventGraph::default();
+                created.compose(&partial).map_err(TaskError::Compose)?;
+                self.partial_graphs.insert(name, created);
+            }
+            Task::PartialCompose { name, partial } => {
+                partial.check_events()?;
+                self.partial_graph(&name)?;
+                self.check_index_clashes(partial.named_events())?;
+                self.partial_graph_mut(&name)?.compose(&partial).map_err(TaskError::Compose)?
+            }
+            Task::PartialPrint { name } => println!("{:#?}", self.partial_graph(&name)?),
+            Task::PartialUnmetPrint { name } => {
+                for ei in self.partial_graph(&name)?.unmet() {
+                    println!("{ei}");
+                }
+            }
+            Task::PartialResolve { name } => {
+                let global = self.global_history()?;
+                let partial = self.partial_graph_mut(&name)?;
+                partial.resolve_from(&global).map_err(TaskError::Compose)?;
+                for ei in partial.unmet() {
+                    println!("still unmet: {ei}");
+                }
+            }
+            Task::CausalClosureCheck => {
+                let global_before = self.global_history()?.closed_before();
+                let mut closed = true;
+                for (agent, local) in self.agent_histories.iter() {
+                    for (pred, succs) in local.missing_predecessors(&global_before) {
+                        let succs: Vec<_> = succs.iter().map(ToString::to_string).collect();
+                        println!("{agent} lacks {pred}, before {}", succs.join(", "));
+                        closed = false;
+                    }
+                }
+                if closed {
+                    println!("every history is causally closed");
+                }
+            }
+            Task::AgentPredecessorsImport { agent } => {
+                let global = self.global_history()?;
+                let imported = global.down_closure(self.agent_history(&agent)?.happen.iter());
+                let local = self.agent_history_mut(&agent)?;
+                local.compose(&imported).map_err(TaskError::Compose)?;
+                // only the agent's own edges can name events no history has
+                for pred in local.missing_predecessors(&global.closed_before()).keys() {
+                    println!("not in any history: {pred}");
+                }
+            }
+            Task::AgentsCompare { agent, other } => {
+                let histories = [self.agent_history(&agent)?, self.agent_history(&other)?];
+                print_comparison([&agent, &other], histories, print_summary)
+                    .map_err(TaskError::Cycle)?
+            }
+            Task::PartialsCompare { name, other } => {
+                let partials = [self.partial_graph(&name)?, self.partial_graph(&other)?];
+                print_comparison([&name, &other], partials, print_partial_summary)
+                    .map_err(TaskError::Cycle)?
+            }
+            Task::PartialDestinationsPrint { name } => {
+                self.partial_graph(&name)?.outcomes(&self.initial_situation)?.print()
+            }
+            Task::AgentEventRecord { agent, event } => {
+                check_event_instances([&EventInstance { event: event.clone(), index: 0 }])?;
+                let local = self.agent_history(&agent)?;
+                let latest: Vec<_> = local.closed_before().maximal().cloned().collect();
+                let ei = EventInstance { event, index: self.allocate_index()? };
+                let graph = EventGraph {
+                    happen: [ei.clone()].into_iter().collect(),
+                    before: latest.into_iter().map(|a| [a, ei.clone()]).collect(),
+                };
+                self.agent_history_mut(&agent)?.compose(&graph).map_err(TaskError::Compose)?;
+                println!("recorded {ei}");
+            }
+        }
+        if mutating {
+            if let Some(path) = &self.autosave {
+                self.save(path)?;
+            }
+        }
+        Ok(())
+    }
+    /// Composes each graph of `loaded` into an empty one, as tasks would, so that
+    /// hand-edited sessions get the same checks for cycles and index clashes.
+    fn rebuild(loaded: Self) -> Result<Self, TaskError> {
+        let mut rebuilt = ReplState {
+            initial_situation: loaded.initial_situation,
+            agent_histories: Default::default(),
+            partial_graphs: Default::default(),
+            next_index: loaded.next_index,
+            autosave: None,
+        };
+        for (agent, local) in loaded.agent_histories {
+            local.check_events()?;
+            rebuilt.check_index_clashes(local.named_events())?;
+            let mut checked = EventGraph::default();
+            checked.compose(&local).map_err(TaskError::Compose)?;
+            rebuilt.agent_histories.insert(agent, checked);
+        }
+        for (name, partial) in loaded.partial_graphs {
+            partial.check_events()?;
+            rebuilt.check_index_clashes(partial.named_events())?;
+            let mut checked = PartialEventGraph::default();
+            checked.compose(&partial).map_err(TaskError::Compose)?;
+            rebuilt.partial_graphs.insert(name, checked);
+        }
+        Ok(rebuilt)
+    }
+    fn save(&self, path: &str) -> Result<(), TaskError> {
+        let pretty_config = ron::ser::PrettyConfig::new();
+        let s = ron::ser::to_string_pretty(self, pretty_config).map_err(TaskError::Ron)?;
+        std::fs::write(path, s).map_err(TaskError::Io)
+    }
+    fn agent_history(&self, agent: &Agent) -> Result<&EventGraph, TaskError> {
+        self.agent_histories
+            .get(agent)
+            .ok_or_else(|| TaskError::UnknownAgent { agent: agent.clone() })
+    }
+    fn agent_history_mut(&mut self, agent: &Agent) -> Result<&mut EventGraph, TaskError> {
+        self.agent_histories
+            .get_mut(agent)
+            .ok_or_else(|| TaskError::UnknownAgent { agent: agent.clone() })
+    }
+    /// Fails if no partial graph is named `name`.
+    fn partial_graph(&self, name: &str) -> Result<&PartialEventGraph, TaskError> {
+        self.partial_graphs
+            .get(name)
+            .ok_or_else(|| TaskError::UnknownPartial { name: name.to_owned() })
+    }
+    fn partial_graph_mut(&mut self, name: &str) -> Result<&mut PartialEventGraph, TaskError> {
+        self.partial_graphs
+            .get_mut(name)
+            .ok_or_else(|| TaskError::UnknownPartial { name: name.to_owned() })
+    }
+    /// Every event named by the agent histories and partial graphs, even those that
+    /// contradict one another.
+    fn known_events(&self) -> HashSet<&EventInstance> {
+        let histories = self.agent_histories.values().flat_map(EventGraph::named_events);
+        let partials = self.partial_graphs.values().flat_map(PartialEventGraph::named_events);
+        histories.chain(partials).collect()
+    }
+    /// Fails if an index of `incoming` names a different event than it does among the
+    /// `known_events`, or among `incoming`.
+    fn check_index_clashes<'a>(
+        &self,
+        incoming: impl IntoIterator<Item = &'a EventInstance>,
+    ) -> Result<(), TaskError> {
+        let clashes = index_clashes(self.known_events(), incoming);
+        if !clashes.is_empty() {
+            return Err(TaskError::Compose(ComposeError::IndexClashes(clashes)));
+        }
+        Ok(())
+    }
+    /// An index above every index in use, and above every index handed out before.
+    fn allocate_index(&mut self) -> Result<u32, TaskError> {
+        let max_in_use = self.known_events().into_iter().map(|ei| ei.index).max();
+        let index = match max_in_use {
+            Some(max_in_use) => max_in_use.checked_add(1).ok_or(TaskError::IndicesExhausted)?,
+            None => 0,
+        };
+        let index = index.max(self.next_index);
+        self.next_index = index.checked_add(1).ok_or(TaskError::IndicesExhausted)?;
+        Ok(index)
+    }
+    /// Composes all agent histories, failing if they contradict one another.
+    fn global_history(&self) -> Result<EventGraph, TaskError> {
+        let mut global = EventGraph::default();
+        for local in self.agent_histories.values() {
+            let cycle = match global.compose(local) {
+                Ok(()) => continue,
+                Err(ComposeError::Cycle(OrderCycle { cycle })) => cycle,
+                Err(e) => return Err(TaskError::Compose(e)),
+            };
+            let edge_agents = (0..cycle.len())
+                .map(|i| {
+                    let edge = [cycle[i].clone(), cycle[(i + 1) % cycle.len()].clone()];
+                    let agents = self
+                        .agent_histories
+                        .iter()
+                        .filter(|(_agent, local)| local.before.contains(&edge))
+                        .map(|(agent, _local)| agent.clone())
+                        .collect();
+                    (edge, agents)
+                })
+                .collect();
+            return Err(TaskError::GlobalConflict(GlobalConflict { cycle, edge_agents }));
+        }
+        Ok(global)
+    }
+}
+
 //////////////////////////////////////////////////////
 
+/// Usage: `mamdex [SCHEMA_PATH] [--batch SCRIPT_PATH]`.
+/// Without `--batch`, tasks are read interactively from stdin.
+/// With it, the tasks in the script are run in order, exiting with an error
+/// on the first task that fails to parse or run.
 pub fn repl() {
-    let initial_history = EventGraph::default();
+    let mut schema_path = None;
+    let mut script_path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--batch" => match args.next() {
+                Some(path) => script_path = Some(path),
+                None => {
+                    eprintln!("usage: mamdex [SCHEMA_PATH] [--batch SCRIPT_PATH]");
+                    std::process::exit(1);
+                }
+            },
+            _ => schema_path = Some(arg),
+        }
+    }
+    let schema = match schema_path {
+        Some(path) => Schema::from_path(&path),
+        None => Schema::from_ron_str(DEFAULT_SCHEMA),
+    };
+    match schema {
+        Ok(schema) => schema.install(),
+        Err(e) => {
+            eprintln!("failed to load schema: {e}");
+            std::process::exit(1);
+        }
+    }
     let mut repl_state = ReplState {
         initial_situation: Situation::default(),
-        agent_histories: enum_map! {
-            Agent::Amy => initial_history.clone(),
-            Agent::Bob => initial_history.clone(),
-            Agent::Dan => initial_history.clone(),
-        },
+        agent_histories: Default::default(),
+        partial_graphs: Default::default(),
+        next_index: 0,
+        autosave: None,
     };
-    let [a, b, c] = [
-        EventInstance { event: Event::SetOwner { owner: 0 }, index: 0 }, // weh
-        EventInstance { event: Event::SetOwner { owner: 1 }, index: 1 }, // weh
-        EventInstance { event: Event::SetOwner { owner: 2 }, index: 2 }, // weh
-    ];
-    repl_state.agent_histories[Agent::Amy].happen.extend([a, c]);
-    repl_state.agent_histories[Agent::Amy].before.extend([[a, c]]);
+    match script_path {
+        Some(path) => {
+            let file = match std::fs::File::open(&path) {
+                Ok(file) => file,
+                Err(e) => {
+                    eprintln!("failed to open script {path}: {e}");
+                    std::process::exit(1);
+                }
+            };
+            let reader = std::io::BufReader::new(file);
+            let mut task_reader =
+                TaskReader { reader, interactive: false, lines_read: 0, first_line: 0 };
+            while let Some(task_result) = task_reader.next_task() {
+                let result = match task_result {
+                    Ok(task) => repl_state.handle_task(task),
+                    Err(TaskReadError::Parse(e)) => {
+                        eprintln!("{path}: failed to parse task at {e}");
+                        std::process::exit(1);
+                    }
+                    Err(TaskReadError::Io(e)) => {
+                        eprintln!("{path}:{}: failed to read: {e}", task_reader.lines_read + 1);
+                        std::process::exit(1);
+                    }
+                };
+                if let Err(e) = result {
+                    eprintln!("{path}:{}: task error: {e}", task_reader.first_line);
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => {
+            let stdin = std::io::stdin();
+            let mut task_reader = TaskReader {
+                reader: stdin.lock(),
+                interactive: true,
+                lines_read: 0,
+                first_line: 0,
+            };
+            while let Some(task_result) = task_reader.next_task() {
+                match task_result {
+                    Ok(task) => {
+                        println!("task: {task:?}");
+                        if let Err(e) = repl_state.handle_task(task) {
+                            println!("task error: {e}");
+                        }
+                    }
+                    Err(TaskReadError::Parse(e)) => println!(
+                        "failed to parse task at {e}\n`Help` prints the shape of each task."
+                    ),
+                    Err(TaskReadError::Io(e)) => {
+                        eprintln!("failed to read a task: {e}");
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
+    }
+}
+// Example tasks are in `scenarios/`, runnable with `--batch`.
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn install_default_schema() {
+        SCHEMA.get_or_init(|| Schema::from_ron_str(DEFAULT_SCHEMA).unwrap());
+    }
 
-    repl_state.agent_histories[Agent::Bob].happen.extend([a, b]);
-    repl_state.agent_histories[Agent::Bob].before.extend([[a, b]]);
-    let stdin = std::io::stdin();
-    let mut stdin_lock = stdin.lock();
+    /// A xorshift generator, so that failing cases can be reproduced.
+    struct Rng(u64);
+    impl Rng {
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % n
+        }
+    }
 
-    let mut buffer = String::new();
-    loop {
-        print!("$ ");
-        std::io::Write::flush(&mut std::io::stdout()).unwrap();
-        std::io::BufRead::read_line(&mut stdin_lock, &mut buffer).unwrap();
-        let task_result = ron::de::from_str::<Task>(&buffer);
-        buffer.clear();
-        println!("task_result: {task_result:#?}");
-        if let Ok(task) = task_result {
-            repl_state.handle_task(task);
-        }
-    }
-}
-/*
-AgentHistoryAdd(agent:Amy,graph:EventGraph(happen:[EventInstance(event:SetOwner(owner:false),index:0),EventInstance(event:SetOwner(owner:false),index:1),EventInstance(event:SetOwner(owner:true),index:2)],before:[(EventInstance(event:SetOwner(owner:false),index:1),EventInstance(event:SetOwner(owner:true),index:2))]))
-AgentHistoryPrint(agent:Amy)
-AgentDestinationsPrint(agent:Amy)
-GlobalHistoryPrint
-GlobalDestinationsPrint
-    SetOwner { owner: bool },
-    BecomeFriends { a: bool, b: bool },
-*/
+    /// An event of the default schema, over few values, so that events often repeat or interfere.
+    fn random_event(rng: &mut Rng) -> Event {
+        let mut value = || rng.below(2) as u32;
+        let (name, args) = match value() + value() {
+            0 => ("Arrive", vec![value()]),
+            1 => ("SetOwner", vec![value()]),
+            _ => ("BecomeFriends", vec![value(), value()]),
+        };
+        Event { name: name.to_owned(), args }
+    }
+
+    fn random_graph(rng: &mut Rng) -> EventGraph {
+        let events: Vec<_> = (0..2 + rng.below(5) as u32)
+            .map(|index| EventInstance { event: random_event(rng), index })
+            .collect();
+        let mut before = HashSet::default();
+        for (i, a) in events.iter().enumerate() {
+            for b in events[i + 1..].iter() {
+                if rng.below(4) == 0 {
+                    before.insert([a.clone(), b.clone()]);
+                }
+            }
+        }
+        EventGraph { happen: events.into_iter().collect(), before }
+    }
+
+    /// The destinations of every ordering of `remaining` that respects `closed_before`.
+    fn brute_force_destinations(
+        closed_before: &ClosedOrder,
+        remaining: &mut Vec<EventInstance>,
+        sit: &Situation,
+        destinations: &mut BTreeSet<Situation>,
+    ) {
+        if remaining.is_empty() {
+            destinations.insert(sit.clone());
+            return;
+        }
+        for i in 0..remaining.len() {
+            let ei = remaining[i].clone();
+            if remaining.iter().any(|pred| closed_before.contains(&[pred.clone(), ei.clone()])) {
+                continue;
+            }
+            if let Some(delta) = sit.try_delta(&ei.event) {
+                let mut next_sit = sit.clone();
+                next_sit.update(&delta);
+                remaining.remove(i);
+                brute_force_destinations(closed_before, remaining, &next_sit, destinations);
+                remaining.insert(i, ei);
+            }
+        }
+    }
+
+    #[test]
+    fn outcomes_match_all_orderings() {
+        install_default_schema();
+        let mut rng = Rng(0x5eed);
+        for case in 0..500 {
+            let graph = random_graph(&mut rng);
+            let mut initial_situation = Situation::default();
+            for who in 0..2 {
+                if rng.below(2) == 0 {
+                    initial_situation.insert(Fact::try_pack(2, &[who]).unwrap(), true);
+                }
+            }
+            let explored: BTreeSet<_> =
+                graph.outcomes(&initial_situation).destinations.into_keys().collect();
+            let mut all = BTreeSet::default();
+            let mut remaining: Vec<_> = graph.happen.iter().cloned().collect();
+            let closed_before = graph.closed_before();
+            brute_force_destinations(&closed_before, &mut remaining, &initial_situation, &mut all);
+            assert_eq!(explored, all, "case {case}: {graph:#?}");
+        }
+    }
+
+    fn event_instance(name: &str, args: &[u32], index: u32) -> EventInstance {
+        EventInstance { event: Event { name: name.to_owned(), args: args.to_vec() }, index }
+    }
+
+    /// The global history of `scenarios/basic.ron`, and its initial situation.
+    fn basic_scenario() -> (EventGraph, Situation) {
+        let set_owner_0 = event_instance("SetOwner", &[0], 0);
+        let set_owner_0_again = event_instance("SetOwner", &[0], 1);
+        let set_owner_1 = event_instance("SetOwner", &[1], 2);
+        let become_friends = event_instance("BecomeFriends", &[0, 1], 3);
+        let graph = EventGraph {
+            before: [[set_owner_0_again.clone(), set_owner_1.clone()]].into_iter().collect(),
+            happen: [set_owner_0, set_owner_0_again, set_owner_1, become_friends]
+                .into_iter()
+                .collect(),
+        };
+        let mut initial_situation = Situation::default();
+        for who in 0..2 {
+            initial_situation.insert(Fact::try_pack(2, &[who]).unwrap(), true);
+        }
+        (graph, initial_situation)
+    }
+
+    #[test]
+    fn divergences_are_decided_by_adjacent_swaps() {
+        install_default_schema();
+        let (graph, initial_situation) = basic_scenario();
+        let divergences = graph.closed_before().divergences(&initial_situation);
+        let owner = |owner| Fact::try_pack(0, &[owner]).unwrap();
+        let facts: Vec<_> = divergences.iter().map(|divergence| divergence.fact).collect();
+        assert_eq!(facts, [owner(0), owner(1)]);
+        // swapping the two instances of `SetOwner(0)` never changes a fact
+        let expected =
+            vec![[event_instance("SetOwner", &[0], 0), event_instance("SetOwner", &[1], 2)]];
+        for divergence in divergences.iter() {
+            assert_eq!(divergence.deciding_pairs, expected);
+        }
+    }
+
+    #[test]
+    fn situations_round_trip_through_ron() {
+        install_default_schema();
+        let mut situation = Situation::default();
+        situation.insert(Fact::try_pack(1, &[1, 2]).unwrap(), true);
+        situation.insert(Fact::try_pack(2, &[3]).unwrap(), true);
+        situation.insert(Fact::try_pack(0, &[0]).unwrap(), false);
+        let text = ron::ser::to_string(&situation).unwrap();
+        assert!(text.contains("friend(a:1,b:2)"), "{text}");
+        let parsed: Situation = ron::de::from_str(&text).unwrap();
+        assert_eq!(parsed, situation);
+    }
+
+    #[test]
+    fn fact_patterns_round_trip_through_ron() {
+        install_default_schema();
+        let pattern: FactPattern = "friend(a=1, b=_)".parse().unwrap();
+        assert!(Fact::try_pack(1, &[1, 3]).unwrap().matches_pattern(pattern));
+        assert!(!Fact::try_pack(1, &[3, 1]).unwrap().matches_pattern(pattern));
+        let text = ron::ser::to_string(&pattern).unwrap();
+        assert_eq!(text, "friend(a:1)");
+        assert_eq!(ron::de::from_str::<FactPattern>(&text).unwrap(), pattern);
+        assert_eq!(text.parse::<FactPattern>().unwrap(), pattern);
+    }
+
+    #[test]
+    fn bad_facts_are_described() {
+        install_default_schema();
+        let out_of_domain = "value 9 of field `who` of kind `exists` is not in 0..4";
+        let cases = [
+            ("nope(who: 0)", "unknown fact kind `nope`", "schema defines no fact kind `nope`"),
+            (
+                "exists(whom: 0)",
+                "kind `exists` has no field `whom`",
+                "kind `exists` has no field `whom`",
+            ),
+            ("exists(who: 9)", out_of_domain, out_of_domain),
+        ];
+        for (text, fact_error, pattern_error) in cases {
+            let e = ron::de::from_str::<Fact>(text).unwrap_err();
+            assert!(e.to_string().contains(fact_error), "{text}: {e}");
+            let e = text.parse::<FactPattern>().unwrap_err();
+            assert!(e.to_string().contains(pattern_error), "{text}: {e}");
+        }
+    }
+
+    /// A history over some of `events`, which are sorted by index, ordering some of them
+    /// by index, so that histories over the same events compose without clashes or cycles.
+    /// Some edges involve events outside `happen`.
+    fn random_sub_history(rng: &mut Rng, events: &[EventInstance]) -> EventGraph {
+        let happen = events.iter().filter(|_ei| rng.below(4) > 0).cloned().collect();
+        let mut before = HashSet::default();
+        for (i, a) in events.iter().enumerate() {
+            for b in events[i + 1..].iter() {
+                if rng.below(4) == 0 {
+                    before.insert([a.clone(), b.clone()]);
+                }
+            }
+        }
+        EventGraph { happen, before }
+    }
+
+    fn closure(graph: &EventGraph) -> (BTreeSet<EventInstance>, BTreeSet<[EventInstance; 2]>) {
+        (graph.happen.iter().cloned().collect(), graph.closed_before().edges().collect())
+    }
+
+    #[test]
+    fn lattice_laws_hold() {
+        install_default_schema();
+        let mut rng = Rng(0x1a77);
+        for case in 0..500 {
+            let mut events: Vec<_> = random_graph(&mut rng).happen.into_iter().collect();
+            events.sort_by_key(|ei| ei.index);
+            let a = random_sub_history(&mut rng, &events);
+            let b = random_sub_history(&mut rng, &events);
+
+            let meet = a.meet(&b);
+            assert!(meet.is_sub_history_of(&a), "case {case}: {a:#?} {b:#?}");
+            assert!(meet.is_sub_history_of(&b), "case {case}: {a:#?} {b:#?}");
+
+            let mut with_a = b.clone();
+            with_a.compose(&a).unwrap();
+            let mut with_difference = b.clone();
+            with_difference.compose(&a.difference(&b)).unwrap();
+            assert_eq!(closure(&with_a), closure(&with_difference), "case {case}: {a:#?} {b:#?}");
+
+            if a.is_prefix_of(&b) {
+                assert!(a.is_sub_history_of(&b), "case {case}: {a:#?} {b:#?}");
+            }
+            // events sorted by index are ordered topologically, so each leading run of
+            // them is closed under predecessors
+            let prefix_len = rng.below(a.happen.len() as u64 + 1) as usize;
+            let mut happen: Vec<_> = a.happen.iter().cloned().collect();
+            happen.sort_by_key(|ei| ei.index);
+            let prefix = EventGraph {
+                happen: happen[..prefix_len].iter().cloned().collect(),
+                before: a.before.clone(),
+            };
+            assert!(prefix.is_prefix_of(&a), "case {case}: {prefix:#?} {a:#?}");
+            assert!(prefix.is_sub_history_of(&a), "case {case}: {prefix:#?} {a:#?}");
+        }
+    }
+
+    #[test]
+    fn every_task_has_an_example() {
+        install_default_schema();
+        let shapes = Task::shapes();
+        for &variant_name in Task::variant_names() {
+            let has_example =
+                shapes.iter().any(|shape| shape.split('(').next() == Some(variant_name));
+            assert!(has_example, "`Task::examples` lacks {variant_name}");
+        }
+    }
+}